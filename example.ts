@@ -33,8 +33,10 @@ main();
 `;
 
 const loader = new StdLoader(
-  (specifier, referrer, isRoot) => {
-    console.log(`RESOLVE REQUEST ${specifier} ${referrer} ${isRoot}`);
+  (specifier, referrer, isMain, isDynImport) => {
+    console.log(
+      `RESOLVE REQUEST ${specifier} ${referrer} ${isMain} ${isDynImport}`
+    );
     return "file:///testmod.js";
   },
   moduleSpecifier => {
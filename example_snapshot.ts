@@ -28,8 +28,10 @@ function main() {
 `;
 
 const loader = new StdLoader(
-  (specifier, referrer, isRoot) => {
-    console.log(`RESOLVE REQUEST ${specifier} ${referrer} ${isRoot}`);
+  (specifier, referrer, isMain, isDynImport) => {
+    console.log(
+      `RESOLVE REQUEST ${specifier} ${referrer} ${isMain} ${isDynImport}`
+    );
     return "file:///testmod.js";
   },
   moduleSpecifier => {
@@ -62,7 +64,7 @@ const customDispatcher = new CustomDispatcher();
 async function main() {
   console.log("PRE EXECUTE");
   await isolate.execute(source);
-  const snapshot = isolate.snapshot();
+  const snapshot = await isolate.snapshot();
   async function loadSnapshotAndExecute() {
     const snapshotIsolate = new Isolate(loader, {
       will_snapshot: false,
@@ -71,10 +73,15 @@ async function main() {
     snapshotIsolate.registerOp("testOp", customDispatcher);
     snapshotIsolate.registerOp("testOpJs", dispatcher);
     await snapshotIsolate.execute("main()");
+    snapshotIsolate.close();
   }
   for (const x of Array(50).keys()) {
     await loadSnapshotAndExecute();
   }
+  snapshot.close();
+  isolate.close();
+  dispatcher.close();
+  loader.close();
   Deno.exit();
 }
 
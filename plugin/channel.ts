@@ -0,0 +1,32 @@
+import { newChannel, channelClose } from "./ops.ts";
+
+export interface ChannelOptions {
+  // Most messages queued at once. Posts to a full channel wait for a
+  // receiver to make room. Unbounded when left out.
+  capacity?: number;
+}
+
+// A message queue between isolates. Attach it to isolates with
+// Isolate.attachChannel, guests then send JSON values or bytes through
+// Deno.channels[name].postMessage(value) and receive them with
+// Deno.channels[name].recv(), which resolves with { done, value } like an
+// iterator. Every message goes to exactly one receiver.
+export class Channel {
+  private readonly rid_: number;
+
+  constructor(options: ChannelOptions = {}) {
+    this.rid_ = newChannel.dispatchSync({ capacity: options.capacity }).rid;
+  }
+
+  get rid(): number {
+    return this.rid_;
+  }
+
+  // Pending and later posts fail, receivers get the messages already queued
+  // and then { done: true }. A guest waiting on recv() keeps its isolate's
+  // event loop running until the channel is closed, see
+  // ExecuteOptions.waitForEventLoop.
+  close(): void {
+    channelClose.dispatchSync({ rid: this.rid_ });
+  }
+}
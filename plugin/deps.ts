@@ -1,2 +1,9 @@
 export { join } from "https://deno.land/std/path/mod.ts";
-export { pluginFilename, DispatchJsonPluginOp } from "../std/plugins/mod.ts";
\ No newline at end of file
+export { pluginFilename, DispatchJsonPluginOp } from "../std/plugins/mod.ts";
+export {
+  test,
+  assert,
+  assertEquals,
+  deferred
+} from "../std/plugins/deps.ts";
+export { JsonOpError } from "../std/plugins/mod.ts";
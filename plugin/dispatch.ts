@@ -1,8 +1,12 @@
 import {
   getDispatcherAccessorPtrs,
+  dispatcherClose,
   newStdDispatcher,
   stdDispatcherWaitForDispatch,
-  stdDispatcherRespond
+  stdDispatcherReadRequest,
+  stdDispatcherRespond,
+  stdDispatcherRespondAsync,
+  stdDispatcherClose
 } from "./ops.ts";
 
 export interface Dispatcher {
@@ -11,11 +15,13 @@ export interface Dispatcher {
 
 export interface DispatcherAccessorPtrs {
   getDispatcher: number;
+  tryGetDispatcher: number;
   insertDispatcher: number;
 }
 
 interface GetDispatcherAccessorPtrsResponse {
   get_dispatcher_ptr: number;
+  try_get_dispatcher_ptr: number;
   insert_dispatcher_ptr: number;
 }
 
@@ -23,28 +29,51 @@ export function getDispatcherAccessors(): DispatcherAccessorPtrs {
   const response = getDispatcherAccessorPtrs.dispatchSync({});
   return {
     getDispatcher: response.get_dispatcher_ptr,
+    tryGetDispatcher: response.try_get_dispatcher_ptr,
     insertDispatcher: response.insert_dispatcher_ptr
   };
 }
 
+// Release a dispatcher registered with the plugin. Isolates that already
+// registered it as an op keep their own reference.
+export function closeDispatcher(dispatcher: Dispatcher): void {
+  dispatcherClose.dispatchSync({ rid: dispatcher.rid });
+}
+
 interface NewStandardDispatcherResponse {
   std_dispatcher_rid: number;
   dispatcher_rid: number;
 }
 
 interface StandardDispatcherWaitForDispatchResponse {
+  closed?: boolean;
   cmd_id: number;
-  data: number[];
-  zero_copy?: number[];
+  data_len: number;
+  zero_copy_len?: number;
+}
+
+export interface StdDispatcherOptions {
+  // Block the guest until the host responds instead of handing it an
+  // async op. Other calls on the isolate wait for the response too.
+  syncOnly?: boolean;
 }
 
+// A response that isn't a Uint8Array, a rejected promise or an exception
+// from ondispatch resolves the guest op with an empty buffer, the same as a
+// closed dispatcher.
 export class StdDispatcher implements Dispatcher {
   private readonly rid_: number;
   private readonly stdDispatcherRid: number;
-  public ondispatch?: (data: Uint8Array, zero_copy?: Uint8Array) => Uint8Array;
+  private closed = false;
+  public ondispatch?: (
+    data: Uint8Array,
+    zero_copy?: Uint8Array
+  ) => Uint8Array | Promise<Uint8Array>;
 
-  constructor() {
-    const response = newStdDispatcher.dispatchSync({});
+  constructor(options: StdDispatcherOptions = {}) {
+    const response = newStdDispatcher.dispatchSync({
+      sync_only: !!options.syncOnly
+    });
     this.rid_ = response.dispatcher_rid;
     this.stdDispatcherRid = response.std_dispatcher_rid;
     this.run();
@@ -54,6 +83,15 @@ export class StdDispatcher implements Dispatcher {
     return this.rid_;
   }
 
+  close(): void {
+    if (this.closed) {
+      return;
+    }
+    this.closed = true;
+    stdDispatcherClose.dispatchSync({ rid: this.stdDispatcherRid });
+    closeDispatcher(this);
+  }
+
   async respond(cmd_id: number, response: Uint8Array) {
     stdDispatcherRespond.dispatchSync({
       rid: this.stdDispatcherRid,
@@ -62,22 +100,81 @@ export class StdDispatcher implements Dispatcher {
   }
 
   private async run() {
-    while (true) {
-      const request = await stdDispatcherWaitForDispatch.dispatchAsync({
-        rid: this.stdDispatcherRid
-      });
-      const data = new Uint8Array(request.data);
-      const zero_copy = request.zero_copy
-        ? new Uint8Array(request.zero_copy)
-        : undefined;
-      const response = this.ondispatch(data, zero_copy);
-      stdDispatcherRespond.dispatchSync(
+    while (!this.closed) {
+      const request: StandardDispatcherWaitForDispatchResponse = await stdDispatcherWaitForDispatch.dispatchAsync(
+        {
+          rid: this.stdDispatcherRid
+        }
+      );
+      if (request.closed) {
+        break;
+      }
+      const payload = new Uint8Array(
+        request.data_len + (request.zero_copy_len || 0)
+      );
+      stdDispatcherReadRequest.dispatchSync(
         {
           rid: this.stdDispatcherRid,
           cmd_id: request.cmd_id
         },
-        response
+        payload
       );
+      const data = payload.subarray(0, request.data_len);
+      const zero_copy =
+        request.zero_copy_len != null
+          ? payload.subarray(request.data_len)
+          : undefined;
+      let response: Uint8Array | Promise<Uint8Array>;
+      try {
+        response = this.ondispatch!(data, zero_copy);
+      } catch (err) {
+        response = Promise.reject(err);
+      }
+      if (!(response instanceof Uint8Array)) {
+        response = Promise.resolve(response);
+      }
+      if (response instanceof Promise) {
+        stdDispatcherRespond.dispatchSync({
+          rid: this.stdDispatcherRid,
+          cmd_id: request.cmd_id
+        });
+        this.respondAsync(request.cmd_id, response);
+      } else {
+        stdDispatcherRespond.dispatchSync(
+          {
+            rid: this.stdDispatcherRid,
+            cmd_id: request.cmd_id
+          },
+          response
+        );
+      }
+    }
+  }
+
+  private async respondAsync(cmd_id: number, response: Promise<Uint8Array>) {
+    let buf: Uint8Array | undefined;
+    try {
+      buf = await response;
+    } catch (err) {
+      buf = undefined;
+    }
+    if (this.closed) {
+      return;
+    }
+    if (buf instanceof Uint8Array) {
+      stdDispatcherRespondAsync.dispatchSync(
+        {
+          rid: this.stdDispatcherRid,
+          cmd_id
+        },
+        buf
+      );
+    } else {
+      stdDispatcherRespondAsync.dispatchSync({
+        rid: this.stdDispatcherRid,
+        cmd_id,
+        closed: true
+      });
     }
   }
 }
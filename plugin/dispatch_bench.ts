@@ -0,0 +1,43 @@
+// Times a guest op sending a 1 MiB payload through a StdDispatcher, end to
+// end from isolate.execute to the host's response. It only uses API that
+// predates the out-buffer transport, so the same file can be run against an
+// older build to compare transports, see the README. Run with
+// `deno -A plugin/dispatch_bench.ts` after `cargo build --release`.
+import { bench, runBenchmarks } from "https://deno.land/std/testing/bench.ts";
+import { Isolate } from "./isolate.ts";
+import { StdDispatcher } from "./dispatch.ts";
+import { StdLoader } from "./modules.ts";
+
+const PAYLOAD_SIZE = 1024 * 1024;
+const RUNS = 20;
+
+const loader = new StdLoader(
+  () => "file:///dispatch_bench.js",
+  moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+);
+const isolate = new Isolate(loader);
+const dispatcher = new StdDispatcher();
+const ack = new Uint8Array(1);
+dispatcher.ondispatch = () => ack;
+isolate.registerOp("bench", dispatcher);
+await isolate.execute(`
+  globalThis.payload = new Uint8Array(${PAYLOAD_SIZE});
+  globalThis.send = () =>
+    Deno.core.dispatch(Deno.core.ops().bench, new Uint8Array(1), payload);
+`);
+
+bench({
+  name: "stdDispatcher1MiB",
+  runs: RUNS,
+  async func(b): Promise<void> {
+    b.start();
+    await isolate.execute("send()");
+    b.stop();
+  }
+});
+
+await runBenchmarks();
+
+isolate.close();
+dispatcher.close();
+loader.close();
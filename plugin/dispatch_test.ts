@@ -0,0 +1,172 @@
+import { test, assertEquals, deferred } from "./deps.ts";
+import { Isolate } from "./isolate.ts";
+import { StdDispatcher } from "./dispatch.ts";
+import { StdLoader } from "./modules.ts";
+
+function newLoader(): StdLoader {
+  return new StdLoader(
+    () => "file:///dispatch_test.js",
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+}
+
+// Calls op `name` once with `bytes` and stores the response as an array in
+// globalThis.response, for sync and async responses alike.
+function callOp(name: string, bytes: number[]): string {
+  return `
+    (() => {
+      const opId = Deno.core.ops()[${JSON.stringify(name)}];
+      Deno.core.setAsyncHandler(opId, buf => {
+        globalThis.response = Array.from(buf);
+      });
+      const buf = Deno.core.dispatch(opId, new Uint8Array(${JSON.stringify(
+        bytes
+      )}));
+      if (buf) {
+        globalThis.response = Array.from(buf);
+      }
+    })();
+  `;
+}
+
+async function readResponse(isolate: Isolate): Promise<unknown> {
+  return isolate.execute("globalThis.response", "response.js", {
+    returnValue: true
+  });
+}
+
+test(async function rejectedResponseResolvesGuestOp() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const rejecting = new StdDispatcher();
+  rejecting.ondispatch = () => Promise.reject(new Error("failed"));
+  const throwing = new StdDispatcher();
+  throwing.ondispatch = () => {
+    throw new Error("failed");
+  };
+  isolate.registerOp("rejecting", rejecting);
+  isolate.registerOp("throwing", throwing);
+
+  // execute() waits for the event loop, so a response that never arrives
+  // would hang here.
+  await isolate.execute(callOp("rejecting", [1]));
+  assertEquals(await readResponse(isolate), []);
+  await isolate.execute("globalThis.response = undefined;");
+  await isolate.execute(callOp("throwing", [1]));
+  assertEquals(await readResponse(isolate), []);
+
+  isolate.close();
+  rejecting.close();
+  throwing.close();
+  loader.close();
+});
+
+test(async function syncOnlyDispatcherRespondsSynchronously() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const immediate = new StdDispatcher({ syncOnly: true });
+  immediate.ondispatch = data => data.map(b => b * 2);
+  const delayed = new StdDispatcher({ syncOnly: true });
+  delayed.ondispatch = async data => data.map(b => b + 1);
+  isolate.registerOp("immediate", immediate);
+  isolate.registerOp("delayed", delayed);
+
+  // The guest thread waits for the host, even when the host answers with a
+  // promise, so dispatch returns the response itself.
+  const callSync = (name: string) =>
+    isolate.execute(
+      `Array.from(Deno.core.dispatch(Deno.core.ops().${name}, new Uint8Array([1, 2])))`,
+      `${name}.js`,
+      { returnValue: true }
+    );
+  assertEquals(await callSync("immediate"), [2, 4]);
+  assertEquals(await callSync("delayed"), [2, 3]);
+
+  isolate.close();
+  immediate.close();
+  delayed.close();
+  loader.close();
+});
+
+test(async function asyncDispatcherHandsGuestAPendingOp() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = data => data.map(b => b * 3);
+  isolate.registerOp("triple", dispatcher);
+
+  assertEquals(
+    await isolate.execute(
+      "Deno.core.dispatch(Deno.core.ops().triple, new Uint8Array([1])) == null",
+      "pending.js",
+      { returnValue: true }
+    ),
+    true
+  );
+  await isolate.execute(callOp("triple", [1, 2]));
+  assertEquals(await readResponse(isolate), [3, 6]);
+
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function megabytePayloadRoundTrip() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher({ syncOnly: true });
+  let received = 0;
+  dispatcher.ondispatch = (data, zero_copy) => {
+    received = data.byteLength + (zero_copy ? zero_copy.byteLength : 0);
+    return zero_copy!;
+  };
+  isolate.registerOp("echo", dispatcher);
+
+  const ok = await isolate.execute(
+    `
+    const payload = new Uint8Array(4 * 1024 * 1024);
+    for (let i = 0; i < payload.length; i++) {
+      payload[i] = i % 251;
+    }
+    const echoed = Deno.core.dispatch(
+      Deno.core.ops().echo,
+      new Uint8Array([1, 2, 3]),
+      payload
+    );
+    echoed.length === payload.length && echoed.every((b, i) => b === i % 251);
+    `,
+    "echo.js",
+    { returnValue: true }
+  );
+  assertEquals(ok, true);
+  assertEquals(received, 3 + 4 * 1024 * 1024);
+
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function respondAfterIsolateClose() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher();
+  const dispatched = deferred<void>();
+  const response = deferred<Uint8Array>();
+  dispatcher.ondispatch = () => {
+    dispatched.resolve();
+    return response;
+  };
+  isolate.registerOp("slow", dispatcher);
+
+  // Fails once the isolate's thread exits with the op still pending.
+  const done = isolate.execute(callOp("slow", [1])).catch(() => {});
+  await dispatched;
+  isolate.close();
+  await done;
+  // The guest op is gone, answering it must not take the host down.
+  response.resolve(new Uint8Array([1]));
+  await response;
+
+  dispatcher.close();
+  loader.close();
+});
@@ -1,4 +1,6 @@
-import { newIsolate, isolateIsComplete, isolateRegisterOp, isolateExecute, isolateExecuteModule, isolateSnapshot } from "./ops.ts";
+import { newIsolate, isolateIsComplete, isolateRegisterOp, isolateUnregisterOp, isolateReplaceOp, isolateAttachChannel, isolateListOps, isolateExecute, isolateReadBytes, isolateReadOutput, isolateAwaitOutput, isolateExecuteModule, isolateCall, isolateSetGlobal, isolateSnapshot, isolateStats, isolateTerminate, isolateClose, moduleNamespace, moduleClose } from "./ops.ts";
+import { JsonOpError } from "./deps.ts";
+import { Channel } from "./channel.ts";
 import { Dispatcher } from "./dispatch.ts";
 import { Loader } from "./modules.ts";
 import { Snapshot } from "./snapshots.ts";
@@ -6,6 +8,26 @@ import { Snapshot } from "./snapshots.ts";
 interface NewIsolateAllOptions {
   will_snapshot: boolean;
   snapshot?: Snapshot;
+  // Default time limit for calls and runs that don't set their own, see
+  // ExecuteOptions.timeoutMs.
+  timeout_ms?: number;
+  // Where guest Deno.core.print and console output goes, see OutputMode.
+  output?: OutputMode;
+  // Run the guest standard library at creation: TextEncoder, TextDecoder
+  // and Deno.jsonOp(name), which has the same dispatchSync and
+  // dispatchAsync as DispatchJsonPluginOp for calling ops registered with
+  // JSON dispatchers. It is kept in snapshots of the isolate.
+  bootstrap?: boolean;
+}
+
+// "inherit" writes to the host process's stdout and stderr, "discard" drops
+// the output, "buffer" keeps it for Isolate.readOutput() and "stream" passes
+// it to Isolate.onoutput as it arrives.
+export type OutputMode = "inherit" | "discard" | "buffer" | "stream";
+
+export interface OutputChunk {
+  stream: "stdout" | "stderr";
+  text: string;
 }
 
 const defaultNewIsolateOptions = {
@@ -17,10 +39,209 @@ export type NewIsolateOptions = Omit<
   keyof typeof defaultNewIsolateOptions
 > & Partial<NewIsolateAllOptions>;
 
+export interface ExecuteOptions {
+  // Send the script's completion value back to the host. JSON values are
+  // returned parsed, ArrayBuffers and typed arrays as a Uint8Array. Scripts
+  // run through an indirect eval in this mode, which changes their scoping:
+  // top level let, const and class bindings don't outlive the call, and
+  // top level vars and functions become deletable globals. Scripts that
+  // declare state for later calls should assign to globalThis instead.
+  returnValue?: boolean;
+  // Terminate the guest if the call runs longer than this. The script and
+  // then the guest code the event loop runs until the call completes, such
+  // as op callbacks, each get this long; time spent waiting on host ops
+  // doesn't count. The call then fails with a "Terminated" error and the
+  // isolate can't be used again.
+  timeoutMs?: number;
+  // Calls on one isolate run one at a time in the order they were made. Set
+  // this to fail with an "IsolateBusy" error instead of waiting behind
+  // another call.
+  failIfBusy?: boolean;
+  // By default the call also waits for the guest's event loop, like run(),
+  // so async work the script started is done by the time it returns. Set
+  // this to false to return once the script itself has run, e.g. when it
+  // starts a receive loop that lives as long as its channel.
+  waitForEventLoop?: boolean;
+}
+
+export interface RunOptions {
+  // Terminate the guest if the event loop spends longer than this running
+  // guest code, see ExecuteOptions.timeoutMs.
+  timeoutMs?: number;
+}
+
+export interface ExecuteModuleOptions {
+  timeoutMs?: number;
+  failIfBusy?: boolean;
+  // See ExecuteOptions.waitForEventLoop.
+  waitForEventLoop?: boolean;
+}
+
+export interface ModuleExport {
+  name: string;
+  // typeof the exported value.
+  type: string;
+  // Missing for values JSON can't represent, like functions.
+  value?: unknown;
+}
+
+export interface ModuleNamespaceOptions {
+  timeoutMs?: number;
+}
+
+// A module evaluated by Isolate.executeModule. Close it once its exports
+// are no longer needed, closing the isolate closes its modules too.
+export class GuestModule {
+  constructor(
+    private readonly rid_: number,
+    // The module's specifier as the loader resolved it.
+    readonly specifier: string,
+  ) {}
+
+  get rid(): number {
+    return this.rid_;
+  }
+
+  // Read the module's exports.
+  async namespace(
+    options: ModuleNamespaceOptions = {},
+  ): Promise<ModuleExport[]> {
+    const response = await rethrowGuestErrors(moduleNamespace.dispatchAsync({
+      rid: this.rid_,
+      timeout_ms: options.timeoutMs,
+    }));
+    const exports = response.exports as CompletionValue;
+    return exports.type === "json" ? exports.value as ModuleExport[] : [];
+  }
+
+  close(): void {
+    moduleClose.dispatchSync({ rid: this.rid_ });
+  }
+}
+
+export interface OpInfo {
+  name: string;
+  opId: number;
+  dispatcherRid: number;
+}
+
+export interface CallOptions {
+  // Call an export of this module, returned by executeModule on the same
+  // isolate, instead of a global function.
+  module?: GuestModule;
+  timeoutMs?: number;
+  failIfBusy?: boolean;
+}
+
+export interface SetGlobalOptions {
+  // Make the global non-writable and non-configurable, so guest code can't
+  // reassign or redefine it, and later setGlobal calls for it fail.
+  readOnly?: boolean;
+  // Deep freeze JSON objects and arrays. Bytes values can't be frozen,
+  // setGlobal fails with an "InvalidInput" error if this is set for one.
+  freeze?: boolean;
+}
+
+// snapshot() resolves once calls made before it are done, unless failIfBusy
+// is set.
+export interface SnapshotOptions {
+  failIfBusy?: boolean;
+}
+
+// Runtime counters for one isolate, see Isolate.stats. V8 heap statistics
+// aren't reported yet: deno_core doesn't hand the plugin the V8 isolate they
+// would be read from.
+export interface IsolateStats {
+  // Ops registered through registerOp.
+  registered_ops: number;
+  // Async ops dispatched by the guest that haven't resolved yet.
+  pending_ops: number;
+  // Calls running on the isolate or waiting for it.
+  queued_calls: number;
+  // Wall clock time spent running guest code in execute, executeModule and
+  // the event loop, including sync ops waiting on the host.
+  busy_time_us: number;
+  // CPU time the guest's thread used over the same stretches, which leaves
+  // out waits on the host. Null on platforms without per thread CPU clocks.
+  cpu_time_us: number | null;
+}
+
+export interface GuestStackFrame {
+  line_number: number;
+  column: number;
+  script_name: string;
+  function_name: string;
+  is_eval: boolean;
+  is_constructor: boolean;
+  is_wasm: boolean;
+}
+
+interface GuestErrorData {
+  message: string;
+  name?: string;
+  script_resource_name?: string;
+  line_number?: number;
+  start_column?: number;
+  end_column?: number;
+  source_line?: string;
+  frames: GuestStackFrame[];
+}
+
+// An exception thrown by guest code, with its location and stack frames
+// rather than just a flattened message.
+export class GuestError extends Error {
+  // The name of the guest's error, only known for promises rejected in a
+  // call or module namespace lookup. It is always undefined for thrown
+  // exceptions: only V8's message text reaches the host, and capturing the
+  // name would mean catching and rethrowing in a guest wrapper, which moves
+  // the reported location into the wrapper. For Error objects the message
+  // starts with the name, e.g. "TypeError: foo".
+  readonly guestName?: string;
+  readonly scriptResourceName?: string;
+  readonly lineNumber?: number;
+  readonly startColumn?: number;
+  readonly endColumn?: number;
+  readonly sourceLine?: string;
+  readonly frames: GuestStackFrame[];
+
+  constructor(data: GuestErrorData) {
+    super(data.message);
+    this.name = "GuestError";
+    this.guestName = data.name;
+    this.scriptResourceName = data.script_resource_name;
+    this.lineNumber = data.line_number;
+    this.startColumn = data.start_column;
+    this.endColumn = data.end_column;
+    this.sourceLine = data.source_line;
+    this.frames = data.frames;
+  }
+}
+
+// Rethrow GuestError op failures as GuestError, everything else as is.
+async function rethrowGuestErrors<T>(promise: Promise<T>): Promise<T> {
+  try {
+    return await promise;
+  } catch (err) {
+    if (err instanceof JsonOpError && err.kind === "GuestError") {
+      throw new GuestError(err.data as GuestErrorData);
+    }
+    throw err;
+  }
+}
+
+type CompletionValue =
+  | { type: "undefined" }
+  | { type: "json"; value: unknown }
+  | { type: "bytes"; id: number; byte_length: number };
+
 export class Isolate {
 
   private readonly rid_: number;
 
+  // Called with guest output in "stream" mode. Output that arrives while
+  // this is unset is dropped.
+  onoutput?: (stream: "stdout" | "stderr", text: string) => void;
+
   constructor(loader: Loader, private readonly options?: NewIsolateOptions) {
     const optionsFinal: NewIsolateAllOptions = {
       ...defaultNewIsolateOptions,
@@ -31,7 +252,13 @@ export class Isolate {
       will_snapshot: optionsFinal.will_snapshot,
       snapshot_rid,
       loader_rid: loader.rid,
+      timeout_ms: optionsFinal.timeout_ms,
+      output: optionsFinal.output,
+      bootstrap: !!optionsFinal.bootstrap,
     }).rid;
+    if (optionsFinal.output === "stream") {
+      this.runOutput();
+    }
   }
 
   get rid(): number {
@@ -46,35 +273,216 @@ export class Isolate {
     });
   }
 
-  async execute(source: string, filename: string = "<anonymous>"): Promise<void> {
-    await isolateExecute.dispatchAsync({
+  // Revoke an op. Guest code still sees its id in Deno.core.ops(), but
+  // until the name is registered again every call gets an immediate
+  // response holding the JSON error envelope
+  // {"err":{"kind":"NotFound","message":...}} in UTF-8. Ops called through
+  // Deno.jsonOp throw a JsonOpError with kind "NotFound".
+  unregisterOp(name: string): void {
+    isolateUnregisterOp.dispatchSync({ rid: this.rid_, name });
+  }
+
+  // Swap the dispatcher behind a registered op, keeping its op id. Calls
+  // already dispatched finish on the old dispatcher.
+  replaceOp(name: string, dispatcher: Dispatcher): void {
+    isolateReplaceOp.dispatchSync({
+      rid: this.rid_,
+      dispatcherRid: dispatcher.rid,
+      name,
+    });
+  }
+
+  // Register an endpoint of channel as op name and define
+  // Deno.channels[name] in the guest. Detach it with unregisterOp(name).
+  async attachChannel(name: string, channel: Channel): Promise<void> {
+    await rethrowGuestErrors(isolateAttachChannel.dispatchAsync({
+      rid: this.rid_,
+      channelRid: channel.rid,
+      name,
+    }));
+  }
+
+  listOps(): OpInfo[] {
+    return isolateListOps.dispatchSync({ rid: this.rid_ }).ops;
+  }
+
+  async execute(
+    source: string,
+    filename: string = "<anonymous>",
+    options: ExecuteOptions = {},
+  ): Promise<unknown> {
+    const response = await rethrowGuestErrors(isolateExecute.dispatchAsync({
       rid: this.rid,
       source,
       filename,
+      return_value: !!options.returnValue,
+      timeout_ms: options.timeoutMs,
+      fail_if_busy: !!options.failIfBusy,
+    }));
+    if (options.waitForEventLoop !== false) {
+      await this.run({ timeoutMs: options.timeoutMs });
+    }
+    if (response.completion) {
+      return this.readCompletion(response.completion);
+    }
+  }
+
+  // Call a guest function with JSON arguments. Uint8Array and ArrayBuffer
+  // arguments arrive in the guest as Uint8Arrays. The result is read back
+  // like ExecuteOptions.returnValue, and promises are awaited first. Unlike
+  // execute, the call returns as soon as its result is settled, even if
+  // other guest work such as a receive loop is still pending; use run to
+  // wait for that.
+  async call(
+    name: string,
+    args: unknown[] = [],
+    options: CallOptions = {},
+  ): Promise<unknown> {
+    const jsonArgs: unknown[] = [];
+    const bytesArgs: Array<{ index: number; byte_length: number }> = [];
+    const chunks: Uint8Array[] = [];
+    args.forEach((arg, index) => {
+      const bytes = arg instanceof ArrayBuffer ? new Uint8Array(arg) : arg;
+      if (bytes instanceof Uint8Array) {
+        jsonArgs.push(null);
+        bytesArgs.push({ index, byte_length: bytes.byteLength });
+        chunks.push(bytes);
+      } else {
+        jsonArgs.push(arg);
+      }
     });
-    await this.run();
+    let input: Uint8Array | undefined;
+    if (chunks.length > 0) {
+      input = new Uint8Array(
+        chunks.reduce((length, chunk) => length + chunk.byteLength, 0),
+      );
+      let offset = 0;
+      for (const chunk of chunks) {
+        input.set(chunk, offset);
+        offset += chunk.byteLength;
+      }
+    }
+    const response = await rethrowGuestErrors(isolateCall.dispatchAsync({
+      rid: this.rid_,
+      name,
+      module_rid: options.module ? options.module.rid : undefined,
+      args: jsonArgs,
+      bytes_args: bytesArgs,
+      timeout_ms: options.timeoutMs,
+      fail_if_busy: !!options.failIfBusy,
+    }, input));
+    return this.readCompletion(response.completion);
+  }
+
+  private readCompletion(completion: CompletionValue): unknown {
+    switch (completion.type) {
+      case "json":
+        return completion.value;
+      case "bytes": {
+        const bytes = new Uint8Array(completion.byte_length);
+        if (bytes.length > 0) {
+          isolateReadBytes.dispatchSync({ rid: this.rid_, id: completion.id }, bytes);
+        }
+        return bytes;
+      }
+      default:
+        return undefined;
+    }
   }
 
-  async executeModule(moduleSpecifier: string) {
-    await isolateExecuteModule.dispatchAsync({
+  async executeModule(
+    moduleSpecifier: string,
+    options: ExecuteModuleOptions = {},
+  ): Promise<GuestModule> {
+    const response = await rethrowGuestErrors(isolateExecuteModule.dispatchAsync({
       rid: this.rid,
       module_specifier: moduleSpecifier,
-    });
-    await this.run();
+      timeout_ms: options.timeoutMs,
+      fail_if_busy: !!options.failIfBusy,
+    }));
+    if (options.waitForEventLoop !== false) {
+      await this.run({ timeoutMs: options.timeoutMs });
+    }
+    return new GuestModule(response.rid, response.specifier);
   }
 
-  snapshot(): Snapshot {
+  // Define a global in the guest from a JSON value, or from bytes which
+  // arrive as a Uint8Array. Globals are part of the guest heap, so they are
+  // kept in snapshots.
+  async setGlobal(
+    name: string,
+    value: unknown,
+    options: SetGlobalOptions = {},
+  ): Promise<void> {
+    const bytes = value instanceof ArrayBuffer ? new Uint8Array(value) : value;
+    const isBytes = bytes instanceof Uint8Array;
+    await rethrowGuestErrors(isolateSetGlobal.dispatchAsync({
+      rid: this.rid_,
+      name,
+      value: isBytes ? null : value,
+      read_only: !!options.readOnly,
+      freeze: !!options.freeze,
+    }, isBytes ? bytes as Uint8Array : undefined));
+  }
+
+  async snapshot(options: SnapshotOptions = {}): Promise<Snapshot> {
     if (this.options.will_snapshot) {
-      const response = isolateSnapshot.dispatchSync({ rid: this.rid });
+      const response = await isolateSnapshot.dispatchAsync({
+        rid: this.rid,
+        fail_if_busy: !!options.failIfBusy,
+      });
       return new Snapshot(response.rid);
     } else {
       throw Error("Snapshots are not enabled for this isolate");
     }
   }
 
-  async run(): Promise<void> {
-    await isolateIsComplete.dispatchAsync({
+  async run(options: RunOptions = {}): Promise<void> {
+    await rethrowGuestErrors(isolateIsComplete.dispatchAsync({
       rid: this.rid_,
-    });
+      timeout_ms: options.timeoutMs,
+    }));
+  }
+
+  stats(): IsolateStats {
+    return isolateStats.dispatchSync({ rid: this.rid_ });
+  }
+
+  // Stop any running guest code. Pending and later calls fail with a
+  // "Terminated" error.
+  terminate(): void {
+    isolateTerminate.dispatchSync({ rid: this.rid_ });
+  }
+
+  // Take the output captured in "buffer" mode since the last call.
+  readOutput(): OutputChunk[] {
+    return isolateReadOutput.dispatchSync({ rid: this.rid_ }).chunks;
+  }
+
+  // Nothing awaits this loop, so errors end it instead of becoming
+  // unhandled rejections: a throwing onoutput, or a BadResource error once
+  // the isolate is closed.
+  private async runOutput() {
+    try {
+      while (true) {
+        const response = await isolateAwaitOutput.dispatchAsync({
+          rid: this.rid_,
+        });
+        if (response.closed) {
+          break;
+        }
+        for (const chunk of response.chunks as OutputChunk[]) {
+          if (this.onoutput) {
+            this.onoutput(chunk.stream, chunk.text);
+          }
+        }
+      }
+    } catch (_) {
+      // Stop streaming.
+    }
+  }
+
+  close(): void {
+    isolateClose.dispatchSync({ rid: this.rid_ });
   }
 }
\ No newline at end of file
@@ -0,0 +1,887 @@
+import { test, assert, assertEquals, deferred, JsonOpError } from "./deps.ts";
+import { Isolate, GuestError } from "./isolate.ts";
+import { StdDispatcher } from "./dispatch.ts";
+import { StdLoader, StdLoaderOptions } from "./modules.ts";
+import { Channel } from "./channel.ts";
+
+function newLoader(): StdLoader {
+  return new StdLoader(
+    () => "file:///isolate_test.js",
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+}
+
+test(async function concurrentCallsRunInOrder() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const results = await Promise.all([
+    isolate.execute("globalThis.order = [1]; 1", "first.js", {
+      returnValue: true
+    }),
+    isolate.execute("order.push(2); order", "second.js", {
+      returnValue: true
+    })
+  ]);
+  assertEquals(results, [1, [1, 2]]);
+  assertEquals(isolate.stats().queued_calls, 0);
+  isolate.close();
+  loader.close();
+});
+
+test(async function failIfBusy() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const first = isolate.execute("for (let i = 0; i < 1e8; i++) {}");
+  let err: Error | undefined;
+  try {
+    await isolate.execute("", "busy.js", { failIfBusy: true });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "IsolateBusy");
+  await first;
+  isolate.close();
+  loader.close();
+});
+
+test(async function runWaitsForLongLivedAsyncOps() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = () =>
+    new Promise(resolve => setTimeout(() => resolve(new Uint8Array([42])), 200));
+  isolate.registerOp("slowOp", dispatcher);
+  await isolate.execute(`
+    const slowOpId = Deno.core.ops().slowOp;
+    Deno.core.setAsyncHandler(slowOpId, buf => {
+      globalThis.received = buf[0];
+    });
+    Deno.core.dispatch(slowOpId, new Uint8Array([1]));
+  `);
+  // execute waits for the event loop, so the response must be in by now.
+  assertEquals(
+    await isolate.execute("received", "check.js", { returnValue: true }),
+    42
+  );
+  assertEquals(isolate.stats().pending_ops, 0);
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function cpuTimeLeavesOutHostWaits() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher({ syncOnly: true });
+  dispatcher.ondispatch = () =>
+    new Promise((resolve) =>
+      setTimeout(() => resolve(new Uint8Array([1])), 200)
+    );
+  isolate.registerOp("slowOp", dispatcher);
+  await isolate.execute(
+    "Deno.core.dispatch(Deno.core.ops().slowOp, new Uint8Array([1]));"
+  );
+  const stats = isolate.stats();
+  assert(stats.busy_time_us >= 200000, `busy_time_us: ${stats.busy_time_us}`);
+  if (stats.cpu_time_us !== null) {
+    assert(stats.cpu_time_us < 100000, `cpu_time_us: ${stats.cpu_time_us}`);
+  }
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function timeoutTerminatesScript() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  let err: Error | undefined;
+  try {
+    await isolate.execute("for (;;) {}", "<loop>", { timeoutMs: 100 });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "Terminated");
+  isolate.close();
+  loader.close();
+});
+
+test(async function isolateTimeoutApplies() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { timeout_ms: 100 });
+  // Quick calls keep sharing the watchdog without tripping it.
+  for (let i = 0; i < 20; i++) {
+    assertEquals(
+      await isolate.execute(`${i}`, "quick.js", { returnValue: true }),
+      i
+    );
+  }
+  let err: Error | undefined;
+  try {
+    await isolate.execute("for (;;) {}", "<loop>");
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "Terminated");
+  isolate.close();
+  loader.close();
+});
+
+test(async function timeoutTerminatesOpCallback() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = () => Promise.resolve(new Uint8Array([1]));
+  isolate.registerOp("loopOp", dispatcher);
+  // The script itself returns at once, the loop runs in the op callback
+  // while the host waits for the event loop.
+  let err: Error | undefined;
+  try {
+    await isolate.execute(`
+      Deno.core.setAsyncHandler(Deno.core.ops().loopOp, () => { for (;;) {} });
+      Deno.core.dispatch(Deno.core.ops().loopOp, new Uint8Array([1]));
+    `, "<loop>", { timeoutMs: 100 });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "Terminated");
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function terminateFailsRun() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = () => new Promise(() => {});
+  isolate.registerOp("neverOp", dispatcher);
+  const done = isolate.execute(
+    "Deno.core.dispatch(Deno.core.ops().neverOp, new Uint8Array([1]));"
+  );
+  setTimeout(() => isolate.terminate(), 100);
+  let err: Error | undefined;
+  try {
+    await done;
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "Terminated");
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function callGlobalFunction() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  await isolate.execute(`
+    function add(a, b) { return a + b; }
+    function byteLength(bytes) { return bytes.length; }
+    async function later(value) { return value; }
+    async function fail() { throw new RangeError("nope"); }
+    function keys(object) { return Object.keys(object); }
+  `);
+  assertEquals(await isolate.call("add", [1, 2]), 3);
+  assertEquals(
+    await isolate.call("keys", [JSON.parse('{"__proto__": 1}')]),
+    ["__proto__"]
+  );
+  assertEquals(await isolate.call("byteLength", [new Uint8Array(5)]), 5);
+  assertEquals(await isolate.call("later", [{ a: 1 }]), { a: 1 });
+  let err: Error | undefined;
+  try {
+    await isolate.call("fail");
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof GuestError, `unexpected error: ${err}`);
+  assertEquals((err as GuestError).guestName, "RangeError");
+  isolate.close();
+  loader.close();
+});
+
+test(async function callSkipsPendingWork() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const response = deferred<Uint8Array>();
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = () => response;
+  isolate.registerOp("pendingOp", dispatcher);
+  await isolate.execute(`
+    function startPending() {
+      const opId = Deno.core.ops().pendingOp;
+      Deno.core.setAsyncHandler(opId, buf => {
+        globalThis.settled = buf[0];
+      });
+      Deno.core.dispatch(opId, new Uint8Array([1]));
+      return "started";
+    }
+  `);
+  // The op stays pending, the call must not wait for it.
+  assertEquals(await isolate.call("startPending"), "started");
+  assertEquals(isolate.stats().pending_ops, 1);
+  response.resolve(new Uint8Array([7]));
+  await isolate.run();
+  assertEquals(
+    await isolate.execute("settled", "check.js", { returnValue: true }),
+    7
+  );
+  isolate.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function callModuleExport() {
+  const loader = new StdLoader(
+    specifier => specifier,
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: "export function greet(name) { return `hello ${name}`; }"
+    })
+  );
+  const isolate = new Isolate(loader);
+  const module = await isolate.executeModule("file:///greet.js");
+  assertEquals(
+    await isolate.call("greet", ["guest"], { module }),
+    "hello guest"
+  );
+  module.close();
+  let err: Error | undefined;
+  try {
+    await isolate.call("greet", ["guest"], { module });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "BadResource");
+  isolate.close();
+  loader.close();
+});
+
+test(async function spoofedCompletionIgnored() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  // A guest reporting through the internal op without the call's token
+  // must not settle the call.
+  await isolate.execute(`
+    globalThis.spoof = () => {
+      const control = new Uint8Array(5);
+      control[0] = 2;
+      const json = '"spoofed"';
+      const chars = new Uint16Array(json.length);
+      for (let i = 0; i < json.length; i++) chars[i] = json.charCodeAt(i);
+      Deno.core.dispatch(Deno.core.ops()["__completion"], control, chars);
+      return new Promise(() => {});
+    };
+  `);
+  let err: Error | undefined;
+  try {
+    await isolate.call("spoof");
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "Unsettled");
+  isolate.close();
+  loader.close();
+});
+
+test(async function moduleNamespaceExports() {
+  const loader = new StdLoader(
+    specifier => specifier,
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: `
+        export const config = { port: 8080 };
+        export const name = "guest";
+        export function handler() {}
+      `
+    })
+  );
+  const isolate = new Isolate(loader);
+  const module = await isolate.executeModule("file:///config.js");
+  const exports = await module.namespace();
+  assertEquals(exports, [
+    { name: "config", type: "object", value: { port: 8080 } },
+    { name: "handler", type: "function" },
+    { name: "name", type: "string", value: "guest" }
+  ]);
+  module.close();
+  isolate.close();
+  loader.close();
+});
+
+test(async function moduleKeepsResolvedSpecifier() {
+  const loader = new StdLoader(
+    specifier =>
+      specifier.startsWith("file:///") ? specifier : `file:///${specifier}.js`,
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: "export const answer = 42;"
+    })
+  );
+  const isolate = new Isolate(loader);
+  const module = await isolate.executeModule("answer");
+  assertEquals(module.specifier, "file:///answer.js");
+  assertEquals(await module.namespace(), [
+    { name: "answer", type: "number", value: 42 }
+  ]);
+  module.close();
+  isolate.close();
+  loader.close();
+});
+
+function dynImportLoader(options: StdLoaderOptions = {}): StdLoader {
+  return new StdLoader(
+    (specifier, referrer, isMain, isDynImport) => {
+      if (specifier === "file:///forbidden.js" && isDynImport) {
+        throw new Error("forbidden");
+      }
+      return specifier;
+    },
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: "export const answer = 42;"
+    }),
+    options
+  );
+}
+
+const dynImportSource = `
+  globalThis.results = [];
+  for (const specifier of ["file:///answer.js", "file:///forbidden.js"]) {
+    import(specifier).then(
+      ns => results.push(ns.answer),
+      err => results.push(err.message)
+    );
+  }
+`;
+
+test(async function dynamicImport() {
+  const loader = dynImportLoader();
+  const isolate = new Isolate(loader);
+  await isolate.execute(dynImportSource);
+  const results = (await isolate.execute("results", "results.js", {
+    returnValue: true
+  })) as unknown[];
+  assertEquals(results.length, 2);
+  assert(results.includes(42));
+  assert(results.some(r => typeof r === "string" && r.includes("forbidden")));
+  isolate.close();
+  loader.close();
+});
+
+test(async function dynamicImportRefused() {
+  const loader = dynImportLoader({ allowDynamicImport: false });
+  const isolate = new Isolate(loader);
+  await isolate.execute(dynImportSource);
+  const results = (await isolate.execute("results", "results.js", {
+    returnValue: true
+  })) as string[];
+  assertEquals(results.length, 2);
+  assert(results.every(r => r.includes("DynamicImportRefused")));
+  isolate.close();
+  loader.close();
+});
+
+test(async function dynamicImportRefusedLeavesModuleLookups() {
+  const loader = new StdLoader(
+    specifier => specifier,
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: "export const answer = 42; export function double(x) { return x * 2; }"
+    }),
+    { allowDynamicImport: false }
+  );
+  const isolate = new Isolate(loader);
+  const module = await isolate.executeModule("file:///answer.js");
+  assertEquals(await isolate.call("double", [21], { module }), 42);
+  assertEquals(await module.namespace(), [
+    { name: "answer", type: "number", value: 42 },
+    { name: "double", type: "function" }
+  ]);
+  // Guest code importing the same module is still refused.
+  await isolate.execute(`
+    import("file:///answer.js").catch(err => { globalThis.refused = err.message; });
+  `);
+  const refused = await isolate.execute("refused", "refused.js", {
+    returnValue: true
+  });
+  assert(
+    String(refused).includes("DynamicImportRefused"),
+    `unexpected result: ${refused}`
+  );
+  module.close();
+  isolate.close();
+  loader.close();
+});
+
+test(async function unregisterReplaceAndListOps() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const first = new StdDispatcher({ syncOnly: true });
+  first.ondispatch = () => new Uint8Array([1]);
+  const second = new StdDispatcher({ syncOnly: true });
+  second.ondispatch = () => new Uint8Array([2]);
+  const callVersion = `
+    const response = Deno.core.dispatch(
+      Deno.core.ops().version,
+      new Uint8Array(1)
+    );
+    response ? Array.from(response) : null;
+  `;
+  const version = () =>
+    isolate.execute(callVersion, "version.js", { returnValue: true });
+
+  isolate.registerOp("version", first);
+  assertEquals(isolate.listOps().map(op => op.name), ["version"]);
+  assertEquals(await version(), [1]);
+
+  isolate.replaceOp("version", second);
+  assertEquals(isolate.listOps()[0].dispatcherRid, second.rid);
+  assertEquals(await version(), [2]);
+
+  isolate.unregisterOp("version");
+  assertEquals(isolate.listOps(), []);
+  const revoked = JSON.parse(
+    new TextDecoder().decode(new Uint8Array((await version()) as number[]))
+  );
+  assertEquals(revoked.err.kind, "NotFound");
+
+  isolate.close();
+  first.close();
+  second.close();
+  loader.close();
+});
+
+test(async function snapshotWaitsForQueuedCalls() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { will_snapshot: true });
+  const dispatcher = new StdDispatcher({ syncOnly: true });
+  // The queued call blocks on a sync op that the host answers from a
+  // timer, so the host must keep running while snapshot() waits.
+  dispatcher.ondispatch = () =>
+    new Promise(resolve => setTimeout(() => resolve(new Uint8Array([7])), 100));
+  isolate.registerOp("slowOp", dispatcher);
+  const first = isolate.execute(
+    "globalThis.answer = Deno.core.dispatch(Deno.core.ops().slowOp, new Uint8Array([1]))[0];"
+  );
+  let err: Error | undefined;
+  try {
+    await isolate.snapshot({ failIfBusy: true });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "IsolateBusy");
+  const snapshot = await isolate.snapshot();
+  await first;
+  const restored = new Isolate(loader, { will_snapshot: false, snapshot });
+  assertEquals(
+    await restored.execute("answer", "restored.js", { returnValue: true }),
+    7
+  );
+  restored.close();
+  isolate.close();
+  snapshot.close();
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function setGlobalKeepsProtoKeys() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  await isolate.setGlobal(
+    "payload",
+    JSON.parse('{"__proto__": {"polluted": true}}')
+  );
+  assertEquals(
+    await isolate.execute(
+      `[
+        Object.getPrototypeOf(payload) === Object.prototype,
+        "polluted" in payload,
+        Object.keys(payload)
+      ]`,
+      "check.js",
+      { returnValue: true }
+    ),
+    [true, false, ["__proto__"]]
+  );
+  isolate.close();
+  loader.close();
+});
+
+test(async function setGlobalSurvivesSnapshot() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { will_snapshot: true });
+  await isolate.setGlobal("config", { limits: [1, 2] }, { freeze: true });
+  await isolate.setGlobal("blob", new Uint8Array([7, 8]), { readOnly: true });
+  assertEquals(
+    await isolate.execute(
+      `"use strict";
+      let frozen = false;
+      try { config.limits.push(3); } catch (e) { frozen = true; }
+      [frozen, Object.getOwnPropertyDescriptor(globalThis, "blob").writable]`,
+      "check.js",
+      { returnValue: true }
+    ),
+    [true, false]
+  );
+  let err: Error | undefined;
+  try {
+    await isolate.setGlobal("blob", null);
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof GuestError, `unexpected error: ${err}`);
+  err = undefined;
+  try {
+    await isolate.setGlobal("frozenBlob", new Uint8Array([1]), { freeze: true });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "InvalidInput");
+
+  const snapshot = await isolate.snapshot();
+  const restored = new Isolate(loader, { will_snapshot: false, snapshot });
+  assertEquals(
+    await restored.execute("[config.limits, Array.from(blob)]", "restored.js", {
+      returnValue: true
+    }),
+    [[1, 2], [7, 8]]
+  );
+  restored.close();
+  isolate.close();
+  snapshot.close();
+  loader.close();
+});
+
+test(async function bufferOutput() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { output: "buffer" });
+  await isolate.execute(`
+    Deno.core.print("out\\n");
+    Deno.core.print("err\\n", true);
+    console.log("log", { a: 1 });
+  `);
+  assertEquals(isolate.readOutput(), [
+    { stream: "stdout", text: "out\n" },
+    { stream: "stderr", text: "err\n" },
+    { stream: "stdout", text: 'log {"a":1}\n' }
+  ]);
+  assertEquals(isolate.readOutput(), []);
+  isolate.close();
+  loader.close();
+});
+
+test(async function streamOutput() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { output: "stream" });
+  const received = new Promise<[string, string]>(resolve => {
+    isolate.onoutput = (stream, text) => resolve([stream, text]);
+  });
+  await isolate.execute(`console.error("oops")`);
+  assertEquals(await received, ["stderr", "oops\n"]);
+  isolate.close();
+  loader.close();
+});
+
+test(async function streamOutputStopsOnErrors() {
+  const loader = newLoader();
+  // A throwing handler and a close right after creation both end the
+  // stream loop without an unhandled rejection.
+  const throwing = new Isolate(loader, { output: "stream" });
+  const called = new Promise<void>(resolve => {
+    throwing.onoutput = () => {
+      resolve();
+      throw new Error("handler failed");
+    };
+  });
+  await throwing.execute(`console.log("first"); console.log("second");`);
+  await called;
+  throwing.close();
+  const closed = new Isolate(loader, { output: "stream" });
+  closed.close();
+  await new Promise(resolve => setTimeout(resolve, 50));
+  loader.close();
+});
+
+test(async function bootstrapJsonOps() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { bootstrap: true });
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+  const ondispatch = (data: Uint8Array) => {
+    const args = JSON.parse(decoder.decode(data));
+    const res =
+      args.n < 0
+        ? { err: { kind: "InvalidInput", message: "negative" } }
+        : { ok: { double: args.n * 2 } };
+    const body = encoder.encode(
+      JSON.stringify({ ...res, promiseId: args.promiseId })
+    );
+    return args.promiseId ? Promise.resolve(body) : body;
+  };
+  // dispatchSync needs a dispatcher that answers synchronously.
+  const syncDispatcher = new StdDispatcher({ syncOnly: true });
+  syncDispatcher.ondispatch = ondispatch;
+  isolate.registerOp("doubleSync", syncDispatcher);
+  const dispatcher = new StdDispatcher();
+  dispatcher.ondispatch = ondispatch;
+  isolate.registerOp("double", dispatcher);
+  const emptyDispatcher = new StdDispatcher({ syncOnly: true });
+  emptyDispatcher.ondispatch = () => new Uint8Array(0);
+  isolate.registerOp("empty", emptyDispatcher);
+  await isolate.execute(`
+    globalThis.results = [
+      Deno.jsonOp("doubleSync").dispatchSync({ n: 2 }).double,
+      new TextDecoder().decode(new TextEncoder().encode("hé\u{1f600}"))
+    ];
+    try {
+      Deno.jsonOp("empty").dispatchSync();
+    } catch (e) {
+      results.push(e.message);
+    }
+    const double = Deno.jsonOp("double");
+    double.dispatchAsync({ n: 3 }).then(r => results.push(r.double));
+    double.dispatchAsync({ n: -1 }).catch(e => results.push(e.kind));
+  `);
+  await isolate.run();
+  assertEquals(
+    await isolate.execute("results", "results.js", { returnValue: true }),
+    [
+      4,
+      "hé\u{1f600}",
+      'op "empty" returned an empty response',
+      6,
+      "InvalidInput"
+    ]
+  );
+  isolate.close();
+  syncDispatcher.close();
+  dispatcher.close();
+  emptyDispatcher.close();
+  loader.close();
+});
+
+test(async function bootstrapRejectsEmptyResponses() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader, { bootstrap: true });
+  const rejecting = new StdDispatcher();
+  rejecting.ondispatch = () => Promise.reject(new Error("host failure"));
+  isolate.registerOp("rejecting", rejecting);
+  const closed = new StdDispatcher();
+  isolate.registerOp("closed", closed);
+  closed.close();
+  await isolate.execute(`
+    globalThis.results = [];
+    Deno.jsonOp("rejecting")
+      .dispatchAsync({})
+      .catch(e => results.push(e.message));
+    Deno.jsonOp("closed")
+      .dispatchAsync({})
+      .catch(e => results.push(e.message));
+  `);
+  assertEquals(
+    await isolate.execute("results", "results.js", { returnValue: true }),
+    [
+      'op "rejecting" returned an empty response',
+      'op "closed" returned an empty response'
+    ]
+  );
+  isolate.close();
+  rejecting.close();
+  loader.close();
+});
+
+test(async function channelBetweenIsolates() {
+  const loader = newLoader();
+  const sender = new Isolate(loader);
+  const receiver = new Isolate(loader);
+  const channel = new Channel({ capacity: 1 });
+  await sender.attachChannel("jobs", channel);
+  await receiver.attachChannel("jobs", channel);
+  // execute waits for the guest's event loop, and the sender blocks on the
+  // full channel until the receiver runs, so both must start together.
+  // Posts are not awaited one by one, the channel keeps their order.
+  await Promise.all([
+    sender.execute(`
+      globalThis.posted = [];
+      for (const message of [{ id: 1 }, new Uint8Array([2]), "three"]) {
+        Deno.channels.jobs.postMessage(message).then(() => posted.push(message));
+      }
+    `),
+    receiver.execute(`
+      globalThis.received = [];
+      (async () => {
+        for (;;) {
+          const { done, value } = await Deno.channels.jobs.recv();
+          if (done) {
+            break;
+          }
+          received.push(value instanceof Uint8Array ? Array.from(value) : value);
+          if (received.length === 3) {
+            break;
+          }
+        }
+      })();
+    `)
+  ]);
+  assertEquals(
+    await receiver.execute("received", "received.js", { returnValue: true }),
+    [{ id: 1 }, [2], "three"]
+  );
+  assertEquals(
+    await sender.execute("posted.length", "posted.js", { returnValue: true }),
+    3
+  );
+
+  channel.close();
+  await sender.execute(`
+    Deno.channels.jobs.postMessage(1).catch(e => {
+      globalThis.closedError = e.message;
+    });
+  `);
+  await sender.run();
+  assertEquals(
+    await sender.execute("closedError", "closed.js", { returnValue: true }),
+    'channel "jobs" is closed'
+  );
+  sender.close();
+  receiver.close();
+  loader.close();
+});
+
+test(async function channelRecvLoopInBackground() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const channel = new Channel();
+  await isolate.attachChannel("events", channel);
+  // The loop outlives the script, so don't wait for the event loop.
+  await isolate.execute(
+    `
+    globalThis.events = [];
+    (async () => {
+      for (;;) {
+        const { done, value } = await Deno.channels.events.recv();
+        events.push(done ? "done" : typeof value);
+        if (done) {
+          break;
+        }
+      }
+    })();
+  `,
+    "loop.js",
+    { waitForEventLoop: false }
+  );
+  await isolate.execute(
+    "Deno.channels.events.postMessage(undefined)",
+    "post.js",
+    { waitForEventLoop: false }
+  );
+  channel.close();
+  await isolate.run();
+  assertEquals(
+    await isolate.execute("events", "events.js", { returnValue: true }),
+    ["undefined", "done"]
+  );
+  isolate.close();
+  loader.close();
+});
+
+test(async function returnValueUnicodeAndBytes() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  assertEquals(
+    await isolate.execute(`({ text: "h\\u00e9llo \\u{1f600}" })`, "text.js", {
+      returnValue: true
+    }),
+    { text: "héllo \u{1f600}" }
+  );
+  assertEquals(
+    await isolate.execute("new Uint8Array([1, 2, 3])", "bytes.js", {
+      returnValue: true
+    }),
+    new Uint8Array([1, 2, 3])
+  );
+  isolate.close();
+  loader.close();
+});
+
+test(async function returnValueUsesEvalScoping() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  await isolate.execute(
+    "let lexical = 1; class Klass {} var plain = 2; plain",
+    "declare.js",
+    { returnValue: true }
+  );
+  assertEquals(
+    await isolate.execute(
+      "[typeof lexical, typeof Klass, delete globalThis.plain]",
+      "check.js",
+      { returnValue: true }
+    ),
+    ["undefined", "undefined", true]
+  );
+  // Without returnValue the script runs as is.
+  await isolate.execute("let kept = 1; var fixed = 2;", "plain.js");
+  assertEquals(
+    await isolate.execute(
+      "[typeof kept, delete globalThis.fixed]",
+      "check.js",
+      { returnValue: true }
+    ),
+    ["number", false]
+  );
+  isolate.close();
+  loader.close();
+});
+
+test(async function thrownErrorLocationAndFrames() {
+  const loader = newLoader();
+  const isolate = new Isolate(loader);
+  const thrown = async (source: string): Promise<GuestError> => {
+    let err: Error | undefined;
+    try {
+      await isolate.execute(source, "frames.js");
+    } catch (e) {
+      err = e;
+    }
+    assert(err instanceof GuestError, `unexpected error: ${err}`);
+    return err as GuestError;
+  };
+
+  const err = await thrown(
+    "function inner() { throw new TypeError('boom'); }\n" +
+      "function outer() { inner(); }\n" +
+      "outer();"
+  );
+  assertEquals(err.message, "TypeError: boom");
+  // Thrown exceptions carry no name, see GuestError.guestName.
+  assertEquals(err.guestName, undefined);
+  assertEquals(err.scriptResourceName, "frames.js");
+  assertEquals(err.lineNumber, 1);
+  assertEquals(
+    err.frames.map(f => [f.function_name, f.script_name, f.line_number]),
+    [
+      ["inner", "frames.js", 1],
+      ["outer", "frames.js", 2],
+      ["", "frames.js", 3]
+    ]
+  );
+
+  // Not an Error, but its message looks like one.
+  const plain = await thrown('throw "RangeError: bar";');
+  assertEquals(plain.message, "RangeError: bar");
+  assertEquals(plain.guestName, undefined);
+
+  isolate.close();
+  loader.close();
+});
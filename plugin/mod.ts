@@ -1,5 +1,37 @@
-export { Dispatcher, StdDispatcher, getDispatcherAccessors } from "./dispatch.ts";
+export {
+  Dispatcher,
+  StdDispatcher,
+  StdDispatcherOptions,
+  closeDispatcher,
+  getDispatcherAccessors
+} from "./dispatch.ts";
 
-export { Isolate } from "./isolate.ts";
+export {
+  Isolate,
+  ExecuteOptions,
+  ExecuteModuleOptions,
+  RunOptions,
+  CallOptions,
+  SetGlobalOptions,
+  OutputMode,
+  OutputChunk,
+  OpInfo,
+  GuestModule,
+  ModuleExport,
+  ModuleNamespaceOptions,
+  SnapshotOptions,
+  IsolateStats,
+  GuestError,
+  GuestStackFrame
+} from "./isolate.ts";
 
-export { Loader, StdLoader } from "./modules.ts";
+export {
+  Loader,
+  StdLoader,
+  StdLoaderOptions,
+  closeLoader
+} from "./modules.ts";
+
+export { Channel, ChannelOptions } from "./channel.ts";
+
+export { resources, ResourceEntry, ResourceKind } from "./resources.ts";
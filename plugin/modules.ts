@@ -1,50 +1,93 @@
 import {
+  loaderClose,
   newStdLoader,
   stdLoaderAwaitResolve,
   stdLoaderRespondResolve,
+  stdLoaderRejectResolve,
   stdLoaderAwaitLoad,
-  stdLoaderRespondLoad
+  stdLoaderRespondLoad,
+  stdLoaderRejectLoad,
+  stdLoaderClose
 } from "./ops.ts";
 
 export interface Loader {
   rid: number;
 }
 
+// Release a loader registered with the plugin. Isolates created with it
+// keep their own reference.
+export function closeLoader(loader: Loader): void {
+  loaderClose.dispatchSync({ rid: loader.rid });
+}
+
 export interface SourceCodeInfo {
   module_name: string;
   code: string;
 }
 
+interface StdLoaderRejection {
+  kind: string;
+  message: string;
+}
+
+// Handler exceptions are forwarded to the guest, keeping the error name as
+// its kind so hosts can throw e.g. a PermissionDenied error from onresolve.
+function toRejection(err: unknown): StdLoaderRejection {
+  if (err instanceof Error) {
+    return { kind: err.name, message: err.message };
+  }
+  return { kind: "Error", message: String(err) };
+}
+
 interface NewStdLoaderResponse {
   std_loader_rid: number;
   loader_rid: number;
 }
 
 interface StdLoaderAwaitResolveResponse {
+  closed?: boolean;
   cmd_id: number;
   specifier: string;
   referrer: string;
-  is_root: boolean;
+  is_main: boolean;
+  is_dyn_import: boolean;
 }
 
 interface StdLoaderAwaitLoadResponse {
+  closed?: boolean;
   cmd_id: number;
   module_specifier: string;
+  maybe_referrer?: string;
+}
+
+export interface StdLoaderOptions {
+  // Set to false to reject every dynamic import() from guests with a
+  // "DynamicImportRefused" error, without calling onresolve. To refuse only
+  // some of them, throw from onresolve when isDynImport is set instead.
+  allowDynamicImport?: boolean;
 }
 
 export class StdLoader implements Loader {
   private readonly rid_: number;
   private readonly stdLoaderRid: number;
+  private closed = false;
 
   constructor(
     public onresolve: (
       specifier: string,
       referrer: string,
-      isRoot: boolean
-    ) => string,
-    public onload: (moduleSpecifier: string) => SourceCodeInfo
+      isMain: boolean,
+      isDynImport: boolean
+    ) => string | Promise<string>,
+    public onload: (
+      moduleSpecifier: string,
+      maybeReferrer?: string
+    ) => SourceCodeInfo,
+    options: StdLoaderOptions = {}
   ) {
-    const response = newStdLoader.dispatchSync({});
+    const response = newStdLoader.dispatchSync({
+      allow_dyn_import: options.allowDynamicImport !== false
+    });
     this.stdLoaderRid = response.std_loader_rid;
     this.rid_ = response.loader_rid;
     this.runResolve();
@@ -55,30 +98,99 @@ export class StdLoader implements Loader {
     return this.rid_;
   }
 
+  close(): void {
+    if (this.closed) {
+      return;
+    }
+    this.closed = true;
+    stdLoaderClose.dispatchSync({ rid: this.stdLoaderRid });
+    closeLoader(this);
+  }
+
   private async runResolve() {
-    while (true) {
-      const request = await stdLoaderAwaitResolve.dispatchAsync({
-        rid: this.stdLoaderRid
-      });
-      const module_specifier = this.onresolve(
+    while (!this.closed) {
+      const request: StdLoaderAwaitResolveResponse = await stdLoaderAwaitResolve.dispatchAsync(
+        {
+          rid: this.stdLoaderRid
+        }
+      );
+      if (request.closed) {
+        break;
+      }
+      // Not awaited so a slow async onresolve doesn't hold up other requests.
+      this.handleResolve(request);
+    }
+  }
+
+  private async handleResolve(request: StdLoaderAwaitResolveResponse) {
+    let module_specifier: string;
+    try {
+      module_specifier = await this.onresolve(
         request.specifier,
         request.referrer,
-        request.is_root
+        request.is_main,
+        request.is_dyn_import
       );
+    } catch (err) {
+      this.rejectResolve(request.cmd_id, err);
+      return;
+    }
+    if (this.closed) {
+      return;
+    }
+    try {
       stdLoaderRespondResolve.dispatchSync({
         rid: this.stdLoaderRid,
         cmd_id: request.cmd_id,
         module_specifier
       });
+    } catch (err) {
+      // E.g. onresolve returned something that isn't a valid URL. This runs
+      // detached from the resolve loop, so nothing else would catch it.
+      this.rejectResolve(request.cmd_id, err);
     }
   }
 
-  private async runLoad() {
-    while (true) {
-      const request = await stdLoaderAwaitLoad.dispatchAsync({
-        rid: this.stdLoaderRid
+  private rejectResolve(cmd_id: number, err: unknown) {
+    if (this.closed) {
+      return;
+    }
+    try {
+      stdLoaderRejectResolve.dispatchSync({
+        rid: this.stdLoaderRid,
+        cmd_id,
+        ...toRejection(err)
       });
-      const source_code_info = this.onload(request.module_specifier);
+    } catch (e) {
+      // The plugin already answered the request, e.g. with the URL error
+      // that got us here.
+    }
+  }
+
+  private async runLoad() {
+    while (!this.closed) {
+      const request: StdLoaderAwaitLoadResponse = await stdLoaderAwaitLoad.dispatchAsync(
+        {
+          rid: this.stdLoaderRid
+        }
+      );
+      if (request.closed) {
+        break;
+      }
+      let source_code_info: SourceCodeInfo;
+      try {
+        source_code_info = this.onload(
+          request.module_specifier,
+          request.maybe_referrer || undefined
+        );
+      } catch (err) {
+        stdLoaderRejectLoad.dispatchSync({
+          rid: this.stdLoaderRid,
+          cmd_id: request.cmd_id,
+          ...toRejection(err)
+        });
+        continue;
+      }
       stdLoaderRespondLoad.dispatchSync({
         rid: this.stdLoaderRid,
         cmd_id: request.cmd_id,
@@ -0,0 +1,150 @@
+import { test, assert, assertEquals, JsonOpError } from "./deps.ts";
+import { Isolate } from "./isolate.ts";
+import { StdLoader } from "./modules.ts";
+
+function namedError(name: string, message: string): Error {
+  const err = new Error(message);
+  err.name = name;
+  return err;
+}
+
+async function executeModuleError(
+  loader: StdLoader,
+  specifier: string
+): Promise<JsonOpError> {
+  const isolate = new Isolate(loader);
+  let err: Error | undefined;
+  try {
+    await isolate.executeModule(specifier);
+  } catch (e) {
+    err = e;
+  }
+  isolate.close();
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  return err as JsonOpError;
+}
+
+test(async function rejectResolveAndLoad() {
+  const loader = new StdLoader(
+    specifier => {
+      if (specifier.includes("secret")) {
+        throw namedError("PermissionDenied", "no secrets");
+      }
+      return specifier;
+    },
+    moduleSpecifier => {
+      if (moduleSpecifier.includes("missing")) {
+        throw namedError("NotFound", "no such module");
+      }
+      return { module_name: moduleSpecifier, code: "" };
+    }
+  );
+
+  const resolveErr = await executeModuleError(loader, "file:///secret.js");
+  assertEquals(resolveErr.kind, "PermissionDenied");
+  assertEquals(resolveErr.message, "no secrets");
+
+  const loadErr = await executeModuleError(loader, "file:///missing.js");
+  assertEquals(loadErr.kind, "NotFound");
+  assertEquals(loadErr.message, "no such module");
+
+  // Non-Error throws are rejected with a generic kind.
+  const plainLoader = new StdLoader(
+    () => {
+      throw "nope";
+    },
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+  const plainErr = await executeModuleError(plainLoader, "file:///main.js");
+  assertEquals(plainErr.kind, "Error");
+  assertEquals(plainErr.message, "nope");
+
+  loader.close();
+  plainLoader.close();
+});
+
+test(async function asyncResolve() {
+  const loader = new StdLoader(
+    async specifier => {
+      await new Promise(resolve => setTimeout(resolve, 10));
+      if (specifier.endsWith("dep.js")) {
+        return "file:///dep.js";
+      }
+      return specifier;
+    },
+    moduleSpecifier => ({
+      module_name: moduleSpecifier,
+      code: moduleSpecifier.endsWith("dep.js")
+        ? "export const value = 1;"
+        : "import { value } from './dep.js'; globalThis.value = value;"
+    })
+  );
+  const isolate = new Isolate(loader);
+  await isolate.executeModule("file:///main.js");
+  assertEquals(
+    await isolate.execute("value", "value.js", { returnValue: true }),
+    1
+  );
+  isolate.close();
+  loader.close();
+});
+
+test(async function asyncResolveInvalidResult() {
+  let result: unknown = "not a url";
+  const loader = new StdLoader(
+    async () => result as string,
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+  // Both must fail the guest's resolve instead of leaving it parked or
+  // throwing in the host.
+  await executeModuleError(loader, "file:///main.js");
+  result = 42;
+  await executeModuleError(loader, "file:///main.js");
+  loader.close();
+});
+
+test(async function loaderSeesImportKindAndReferrer() {
+  const resolves: Array<[string, string, boolean, boolean]> = [];
+  const loads: Array<[string, string | undefined]> = [];
+  const loader = new StdLoader(
+    (specifier, referrer, isMain, isDynImport) => {
+      resolves.push([specifier, referrer, isMain, isDynImport]);
+      return new URL(specifier, referrer || undefined).href;
+    },
+    (moduleSpecifier, maybeReferrer) => {
+      loads.push([moduleSpecifier, maybeReferrer]);
+      const code =
+        moduleSpecifier === "file:///main.js"
+          ? "import './static.js'; import('./dynamic.js');"
+          : "";
+      return { module_name: moduleSpecifier, code };
+    }
+  );
+  const isolate = new Isolate(loader);
+  await isolate.executeModule("file:///main.js");
+  await isolate.run();
+
+  const find = (specifier: string) =>
+    resolves.find(([s]) => s === specifier)!;
+  assertEquals(find("file:///main.js").slice(2), [true, false]);
+  assertEquals(find("./static.js"), [
+    "./static.js",
+    "file:///main.js",
+    false,
+    false
+  ]);
+  assertEquals(find("./dynamic.js"), [
+    "./dynamic.js",
+    "file:///main.js",
+    false,
+    true
+  ]);
+
+  const referrerOf = (specifier: string) =>
+    loads.find(([s]) => s === specifier)![1];
+  assertEquals(referrerOf("file:///main.js"), undefined);
+  assertEquals(referrerOf("file:///static.js"), "file:///main.js");
+
+  isolate.close();
+  loader.close();
+});
@@ -31,25 +31,55 @@ const plugin = openPlugin(
 
 // StandardDispatcher ops
 export const getDispatcherAccessorPtrs = new DispatchJsonPluginOp(plugin.ops.getDispatcherAccessorPtrs);
+export const dispatcherClose = new DispatchJsonPluginOp(plugin.ops.dispatcherClose);
 export const newStdDispatcher = new DispatchJsonPluginOp(plugin.ops.newStdDispatcher);
 export const stdDispatcherWaitForDispatch = new DispatchJsonPluginOp(plugin.ops.stdDispatcherWaitForDispatch);
+export const stdDispatcherReadRequest = new DispatchJsonPluginOp(plugin.ops.stdDispatcherReadRequest);
 export const stdDispatcherRespond = new DispatchJsonPluginOp(plugin.ops.stdDispatcherRespond);
+export const stdDispatcherRespondAsync = new DispatchJsonPluginOp(plugin.ops.stdDispatcherRespondAsync);
+export const stdDispatcherClose = new DispatchJsonPluginOp(plugin.ops.stdDispatcherClose);
 
 // Isolate ops
 export const newIsolate = new DispatchJsonPluginOp(plugin.ops.newIsolate);
 export const isolateIsComplete = new DispatchJsonPluginOp(plugin.ops.isolateIsComplete);
 export const isolateRegisterOp = new DispatchJsonPluginOp(plugin.ops.isolateRegisterOp);
+export const isolateUnregisterOp = new DispatchJsonPluginOp(plugin.ops.isolateUnregisterOp);
+export const isolateReplaceOp = new DispatchJsonPluginOp(plugin.ops.isolateReplaceOp);
+export const isolateAttachChannel = new DispatchJsonPluginOp(plugin.ops.isolateAttachChannel);
+export const isolateListOps = new DispatchJsonPluginOp(plugin.ops.isolateListOps);
 export const isolateExecute = new DispatchJsonPluginOp(plugin.ops.isolateExecute);
+export const isolateReadBytes = new DispatchJsonPluginOp(plugin.ops.isolateReadBytes);
 export const isolateExecuteModule = new DispatchJsonPluginOp(plugin.ops.isolateExecuteModule);
+export const isolateCall = new DispatchJsonPluginOp(plugin.ops.isolateCall);
+export const isolateReadOutput = new DispatchJsonPluginOp(plugin.ops.isolateReadOutput);
+export const isolateAwaitOutput = new DispatchJsonPluginOp(plugin.ops.isolateAwaitOutput);
+export const isolateSetGlobal = new DispatchJsonPluginOp(plugin.ops.isolateSetGlobal);
 export const isolateSnapshot = new DispatchJsonPluginOp(plugin.ops.isolateSnapshot);
+export const isolateStats = new DispatchJsonPluginOp(plugin.ops.isolateStats);
+export const isolateTerminate = new DispatchJsonPluginOp(plugin.ops.isolateTerminate);
+export const isolateClose = new DispatchJsonPluginOp(plugin.ops.isolateClose);
 
 // Module ops
+export const moduleNamespace = new DispatchJsonPluginOp(plugin.ops.moduleNamespace);
+export const moduleClose = new DispatchJsonPluginOp(plugin.ops.moduleClose);
+export const loaderClose = new DispatchJsonPluginOp(plugin.ops.loaderClose);
 export const newStdLoader = new DispatchJsonPluginOp(plugin.ops.newStdLoader);
 export const stdLoaderAwaitResolve = new DispatchJsonPluginOp(plugin.ops.stdLoaderAwaitResolve);
 export const stdLoaderRespondResolve = new DispatchJsonPluginOp(plugin.ops.stdLoaderRespondResolve);
+export const stdLoaderRejectResolve = new DispatchJsonPluginOp(plugin.ops.stdLoaderRejectResolve);
 export const stdLoaderAwaitLoad = new DispatchJsonPluginOp(plugin.ops.stdLoaderAwaitLoad);
 export const stdLoaderRespondLoad = new DispatchJsonPluginOp(plugin.ops.stdLoaderRespondLoad);
+export const stdLoaderRejectLoad = new DispatchJsonPluginOp(plugin.ops.stdLoaderRejectLoad);
+export const stdLoaderClose = new DispatchJsonPluginOp(plugin.ops.stdLoaderClose);
+
+// Resource ops
+export const resources = new DispatchJsonPluginOp(plugin.ops.resources);
 
 // Snapshot ops
 export const newSnapshot = new DispatchJsonPluginOp(plugin.ops.newSnapshot);
-export const snapshotRead = new DispatchJsonPluginOp(plugin.ops.snapshotRead);
\ No newline at end of file
+export const snapshotRead = new DispatchJsonPluginOp(plugin.ops.snapshotRead);
+export const snapshotClose = new DispatchJsonPluginOp(plugin.ops.snapshotClose);
+
+// Channel ops
+export const newChannel = new DispatchJsonPluginOp(plugin.ops.newChannel);
+export const channelClose = new DispatchJsonPluginOp(plugin.ops.channelClose);
@@ -0,0 +1,21 @@
+import { resources as resourcesOp } from "./ops.ts";
+
+export type ResourceKind =
+  | "dispatcher"
+  | "stdDispatcher"
+  | "loader"
+  | "stdLoader"
+  | "isolate"
+  | "module"
+  | "snapshot"
+  | "channel";
+
+export interface ResourceEntry {
+  rid: number;
+  kind: ResourceKind;
+}
+
+// List every resource the plugin is currently holding on to.
+export function resources(): ResourceEntry[] {
+  return resourcesOp.dispatchSync({});
+}
@@ -0,0 +1,300 @@
+import { test, assert, assertEquals, JsonOpError } from "./deps.ts";
+import {
+  dispatcherClose,
+  stdDispatcherWaitForDispatch,
+  stdDispatcherReadRequest,
+  stdDispatcherRespond,
+  stdDispatcherRespondAsync,
+  stdDispatcherClose,
+  newIsolate,
+  isolateIsComplete,
+  isolateRegisterOp,
+  isolateUnregisterOp,
+  isolateReplaceOp,
+  isolateListOps,
+  isolateExecute,
+  isolateExecuteModule,
+  isolateCall,
+  isolateSetGlobal,
+  isolateSnapshot,
+  isolateStats,
+  isolateReadOutput,
+  isolateAwaitOutput,
+  isolateTerminate,
+  isolateClose,
+  loaderClose,
+  stdLoaderAwaitResolve,
+  stdLoaderRespondResolve,
+  stdLoaderAwaitLoad,
+  stdLoaderRespondLoad,
+  stdLoaderClose,
+  newSnapshot,
+  snapshotRead,
+  snapshotClose,
+  channelClose,
+  isolateAttachChannel,
+  moduleNamespace,
+  moduleClose
+} from "./ops.ts";
+import { StdDispatcher } from "./dispatch.ts";
+import { StdLoader } from "./modules.ts";
+import { resources } from "./resources.ts";
+
+const BAD_RID = 0xffffff;
+
+function assertBadResource(err: Error): void {
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "BadResource");
+}
+
+function expectBadResourceSync(fn: () => unknown): void {
+  let err: Error | undefined;
+  try {
+    fn();
+  } catch (e) {
+    err = e;
+  }
+  assert(err != null, "expected a BadResource error");
+  assertBadResource(err!);
+}
+
+async function expectBadResourceAsync(
+  fn: () => Promise<unknown>
+): Promise<void> {
+  let err: Error | undefined;
+  try {
+    await fn();
+  } catch (e) {
+    err = e;
+  }
+  assert(err != null, "expected a BadResource error");
+  assertBadResource(err!);
+}
+
+test(function dispatchOpsBadResource() {
+  expectBadResourceSync(() => dispatcherClose.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() =>
+    stdDispatcherReadRequest.dispatchSync(
+      { rid: BAD_RID, cmd_id: 0 },
+      new Uint8Array(1)
+    )
+  );
+  expectBadResourceSync(() =>
+    stdDispatcherRespond.dispatchSync(
+      { rid: BAD_RID, cmd_id: 0 },
+      new Uint8Array(1)
+    )
+  );
+  expectBadResourceSync(() =>
+    stdDispatcherRespondAsync.dispatchSync(
+      { rid: BAD_RID, cmd_id: 0 },
+      new Uint8Array(1)
+    )
+  );
+  expectBadResourceSync(() =>
+    stdDispatcherClose.dispatchSync({ rid: BAD_RID })
+  );
+});
+
+test(async function dispatchWaitBadResource() {
+  await expectBadResourceAsync(() =>
+    stdDispatcherWaitForDispatch.dispatchAsync({ rid: BAD_RID })
+  );
+});
+
+test(function isolateOpsBadResource() {
+  expectBadResourceSync(() =>
+    newIsolate.dispatchSync({ will_snapshot: false, loader_rid: BAD_RID })
+  );
+  const loader = new StdLoader(
+    () => "file:///bad_resource.js",
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+  expectBadResourceSync(() =>
+    newIsolate.dispatchSync({
+      will_snapshot: false,
+      snapshot_rid: BAD_RID,
+      loader_rid: loader.rid
+    })
+  );
+  const dispatcher = new StdDispatcher();
+  expectBadResourceSync(() =>
+    isolateRegisterOp.dispatchSync({
+      rid: BAD_RID,
+      dispatcherRid: dispatcher.rid,
+      name: "badResource"
+    })
+  );
+  expectBadResourceSync(() =>
+    isolateUnregisterOp.dispatchSync({ rid: BAD_RID, name: "badResource" })
+  );
+  expectBadResourceSync(() =>
+    isolateReplaceOp.dispatchSync({
+      rid: BAD_RID,
+      dispatcherRid: dispatcher.rid,
+      name: "badResource"
+    })
+  );
+  expectBadResourceSync(() => isolateListOps.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() => isolateStats.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() =>
+    isolateReadOutput.dispatchSync({ rid: BAD_RID })
+  );
+  expectBadResourceSync(() => isolateTerminate.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() => isolateClose.dispatchSync({ rid: BAD_RID }));
+  dispatcher.close();
+  loader.close();
+});
+
+test(async function isolateAsyncOpsBadResource() {
+  await expectBadResourceAsync(() =>
+    isolateIsComplete.dispatchAsync({ rid: BAD_RID })
+  );
+  await expectBadResourceAsync(() =>
+    isolateExecute.dispatchAsync({
+      rid: BAD_RID,
+      filename: "bad_resource.js",
+      source: ""
+    })
+  );
+  await expectBadResourceAsync(() =>
+    isolateExecuteModule.dispatchAsync({
+      rid: BAD_RID,
+      module_specifier: "file:///bad_resource.js"
+    })
+  );
+  await expectBadResourceAsync(() =>
+    isolateCall.dispatchAsync({
+      rid: BAD_RID,
+      name: "badResource",
+      args: [],
+      bytes_args: []
+    })
+  );
+  await expectBadResourceAsync(() =>
+    isolateSetGlobal.dispatchAsync({ rid: BAD_RID, name: "badResource" })
+  );
+  await expectBadResourceAsync(() =>
+    isolateAwaitOutput.dispatchAsync({ rid: BAD_RID })
+  );
+  await expectBadResourceAsync(() =>
+    isolateSnapshot.dispatchAsync({ rid: BAD_RID })
+  );
+});
+
+test(function moduleOpsBadResource() {
+  expectBadResourceSync(() => moduleClose.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() => loaderClose.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() =>
+    stdLoaderRespondResolve.dispatchSync({
+      rid: BAD_RID,
+      cmd_id: 0,
+      module_specifier: "file:///bad_resource.js"
+    })
+  );
+  expectBadResourceSync(() =>
+    stdLoaderRespondLoad.dispatchSync({
+      rid: BAD_RID,
+      cmd_id: 0,
+      module_name: "file:///bad_resource.js",
+      code: ""
+    })
+  );
+  expectBadResourceSync(() => stdLoaderClose.dispatchSync({ rid: BAD_RID }));
+});
+
+test(async function moduleAsyncOpsBadResource() {
+  await expectBadResourceAsync(() =>
+    moduleNamespace.dispatchAsync({ rid: BAD_RID })
+  );
+  await expectBadResourceAsync(() =>
+    stdLoaderAwaitResolve.dispatchAsync({ rid: BAD_RID })
+  );
+  await expectBadResourceAsync(() =>
+    stdLoaderAwaitLoad.dispatchAsync({ rid: BAD_RID })
+  );
+});
+
+test(function snapshotOpsBadResource() {
+  expectBadResourceSync(() => snapshotRead.dispatchSync({ rid: BAD_RID }));
+  expectBadResourceSync(() => snapshotClose.dispatchSync({ rid: BAD_RID }));
+});
+
+test(async function channelOpsBadResource() {
+  expectBadResourceSync(() => channelClose.dispatchSync({ rid: BAD_RID }));
+  await expectBadResourceAsync(() =>
+    isolateAttachChannel.dispatchAsync({
+      rid: BAD_RID,
+      channelRid: BAD_RID,
+      name: "badResource"
+    })
+  );
+});
+
+test(function mismatchedKindBadResource() {
+  const loader = new StdLoader(
+    () => "file:///bad_resource.js",
+    moduleSpecifier => ({ module_name: moduleSpecifier, code: "" })
+  );
+  expectBadResourceSync(() =>
+    isolateRegisterOp.dispatchSync({
+      rid: BAD_RID,
+      dispatcherRid: loader.rid,
+      name: "badResource"
+    })
+  );
+  expectBadResourceSync(() => dispatcherClose.dispatchSync({ rid: loader.rid }));
+  loader.close();
+});
+
+test(function staleRidBadResource() {
+  const first = new StdDispatcher();
+  first.close();
+  // The freed slot may be reused, the old rid must not resolve to it.
+  const second = new StdDispatcher();
+  expectBadResourceSync(() => dispatcherClose.dispatchSync({ rid: first.rid }));
+  second.close();
+});
+
+test(function ridsNeverRepeat() {
+  // Enough to run a reused slot past its last generation.
+  const seen = new Set<number>();
+  for (let i = 0; i < 5000; i++) {
+    const dispatcher = new StdDispatcher();
+    assert(!seen.has(dispatcher.rid), `rid ${dispatcher.rid} handed out twice`);
+    seen.add(dispatcher.rid);
+    dispatcher.close();
+  }
+});
+
+test(function listResources() {
+  const dispatcher = new StdDispatcher();
+  const entries = resources();
+  assert(
+    entries.some(e => e.rid === dispatcher.rid && e.kind === "dispatcher")
+  );
+  dispatcher.close();
+  assert(!resources().some(e => e.rid === dispatcher.rid));
+});
+
+test(function respondAsyncWithoutBuffer() {
+  let err: Error | undefined;
+  try {
+    stdDispatcherRespondAsync.dispatchSync({ rid: BAD_RID, cmd_id: 0 });
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "InvalidInput");
+});
+
+test(function newSnapshotWithoutBuffer() {
+  let err: Error | undefined;
+  try {
+    newSnapshot.dispatchSync({});
+  } catch (e) {
+    err = e;
+  }
+  assert(err instanceof JsonOpError, `unexpected error: ${err}`);
+  assertEquals((err as JsonOpError).kind, "InvalidInput");
+});
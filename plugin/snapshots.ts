@@ -1,4 +1,4 @@
-import { newSnapshot, snapshotRead } from "./ops.ts";
+import { newSnapshot, snapshotRead, snapshotClose } from "./ops.ts";
 
 export class Snapshot {
   constructor(private readonly _rid: number) {}
@@ -11,6 +11,10 @@ export class Snapshot {
     const response = snapshotRead.dispatchSync({ rid: this._rid });
     return new Uint8Array(response.data);
   }
+
+  close(): void {
+    snapshotClose.dispatchSync({ rid: this._rid });
+  }
 }
 
 export class StdSnapshot extends Snapshot {
@@ -0,0 +1,243 @@
+// Optional guest standard library, run at isolate creation when the host
+// asks for it. Gives guests TextEncoder/TextDecoder and Deno.jsonOp, which
+// calls host ops using the deno_dispatch_json wire format: JSON arguments
+// with a promiseId for async calls, answered by an {ok} or {err} envelope.
+// Skips itself when already present, e.g. in an isolate made from a
+// snapshot.
+(() => {
+  if (Deno.jsonOp) {
+    return;
+  }
+
+  class TextEncoder {
+    get encoding() {
+      return "utf-8";
+    }
+
+    encode(input = "") {
+      const str = String(input);
+      const bytes = [];
+      for (let i = 0; i < str.length; i++) {
+        let c = str.charCodeAt(i);
+        if (c >= 0xd800 && c < 0xdc00 && i + 1 < str.length) {
+          const next = str.charCodeAt(i + 1);
+          if (next >= 0xdc00 && next < 0xe000) {
+            c = 0x10000 + ((c - 0xd800) << 10) + (next - 0xdc00);
+            i++;
+          }
+        }
+        if (c >= 0xd800 && c < 0xe000) {
+          // Lone surrogate.
+          c = 0xfffd;
+        }
+        if (c < 0x80) {
+          bytes.push(c);
+        } else if (c < 0x800) {
+          bytes.push(0xc0 | (c >> 6), 0x80 | (c & 0x3f));
+        } else if (c < 0x10000) {
+          bytes.push(
+            0xe0 | (c >> 12),
+            0x80 | ((c >> 6) & 0x3f),
+            0x80 | (c & 0x3f)
+          );
+        } else {
+          bytes.push(
+            0xf0 | (c >> 18),
+            0x80 | ((c >> 12) & 0x3f),
+            0x80 | ((c >> 6) & 0x3f),
+            0x80 | (c & 0x3f)
+          );
+        }
+      }
+      return new Uint8Array(bytes);
+    }
+  }
+
+  class TextDecoder {
+    get encoding() {
+      return "utf-8";
+    }
+
+    decode(input) {
+      if (input === undefined) {
+        return "";
+      }
+      const bytes = ArrayBuffer.isView(input)
+        ? new Uint8Array(input.buffer, input.byteOffset, input.byteLength)
+        : new Uint8Array(input);
+      let out = "";
+      let i = 0;
+      while (i < bytes.length) {
+        const b = bytes[i];
+        let needed = 0;
+        let c = 0;
+        let min = 0;
+        if (b < 0x80) {
+          c = b;
+        } else if (b >= 0xc2 && b < 0xe0) {
+          needed = 1;
+          c = b & 0x1f;
+          min = 0x80;
+        } else if (b >= 0xe0 && b < 0xf0) {
+          needed = 2;
+          c = b & 0x0f;
+          min = 0x800;
+        } else if (b >= 0xf0 && b < 0xf5) {
+          needed = 3;
+          c = b & 0x07;
+          min = 0x10000;
+        } else {
+          out += "�";
+          i++;
+          continue;
+        }
+        let j = 1;
+        for (; j <= needed; j++) {
+          const next = bytes[i + j];
+          if (next === undefined || (next & 0xc0) !== 0x80) {
+            break;
+          }
+          c = (c << 6) | (next & 0x3f);
+        }
+        if (
+          j <= needed ||
+          c < min ||
+          c > 0x10ffff ||
+          (c >= 0xd800 && c < 0xe000)
+        ) {
+          out += "�";
+          i += j;
+          continue;
+        }
+        out += String.fromCodePoint(c);
+        i += needed + 1;
+      }
+      return out;
+    }
+  }
+
+  if (typeof globalThis.TextEncoder === "undefined") {
+    globalThis.TextEncoder = TextEncoder;
+  }
+  if (typeof globalThis.TextDecoder === "undefined") {
+    globalThis.TextDecoder = TextDecoder;
+  }
+
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  // Thrown for op errors that carry a kind, like the host's JsonOpError.
+  class JsonOpError extends Error {
+    constructor(kind, message, data) {
+      super(message);
+      this.name = kind;
+      this.kind = kind;
+      this.data = data;
+    }
+  }
+
+  function unwrapResponse(res) {
+    if (res.err != null) {
+      if (res.err.kind != null) {
+        throw new JsonOpError(res.err.kind, res.err.message, res.err.data);
+      }
+      throw new Error(res.err.message);
+    }
+    return res.ok;
+  }
+
+  // JSON ops always answer with a JSON body. An empty buffer is what a
+  // closed StdDispatcher, or one whose handler failed, responds with.
+  function emptyResponseError(name) {
+    return new Error(`op "${name}" returned an empty response`);
+  }
+
+  function decode(ui8, name) {
+    if (ui8.byteLength === 0) {
+      throw emptyResponseError(name);
+    }
+    return JSON.parse(decoder.decode(ui8));
+  }
+
+  // Promise ids are unique across ops, so one table serves them all.
+  const promiseTable = new Map();
+  let nextPromiseId = 1;
+  // Pending promise ids per op id, oldest first. An empty response carries
+  // no promise id, so it settles the op's oldest pending promise.
+  const pendingByOp = new Map();
+
+  function handleAsync(id, name, resUi8) {
+    const pending = pendingByOp.get(id);
+    if (resUi8.byteLength === 0) {
+      const promiseId = pending.shift();
+      if (promiseId !== undefined) {
+        const { reject } = promiseTable.get(promiseId);
+        promiseTable.delete(promiseId);
+        reject(emptyResponseError(name));
+      }
+      return;
+    }
+    const res = JSON.parse(decoder.decode(resUi8));
+    const entry = promiseTable.get(res.promiseId);
+    if (entry) {
+      promiseTable.delete(res.promiseId);
+      pending.splice(pending.indexOf(res.promiseId), 1);
+      entry.resolve(res);
+    }
+  }
+
+  // Ops may be registered after this script runs, so ids are looked up on
+  // each call.
+  function opId(name) {
+    const id = Deno.core.ops()[name];
+    if (id === undefined) {
+      throw new Error(`op "${name}" is not registered`);
+    }
+    if (!pendingByOp.has(id)) {
+      Deno.core.setAsyncHandler(id, resUi8 => handleAsync(id, name, resUi8));
+      pendingByOp.set(id, []);
+    }
+    return id;
+  }
+
+  function jsonOp(name) {
+    return {
+      dispatchSync(args = {}, zeroCopy) {
+        const resUi8 = Deno.core.dispatch(
+          opId(name),
+          encoder.encode(JSON.stringify(args)),
+          zeroCopy
+        );
+        // deno_core hands an empty sync response over as no response.
+        if (resUi8 == null) {
+          throw emptyResponseError(name);
+        }
+        return unwrapResponse(decode(resUi8, name));
+      },
+
+      // No response means the op went async. A sync only dispatcher that
+      // fails also answers with nothing, so use dispatchSync with those.
+      async dispatchAsync(args = {}, zeroCopy) {
+        const id = opId(name);
+        const promiseId = nextPromiseId++;
+        const res = await new Promise((resolve, reject) => {
+          const argsUi8 = encoder.encode(
+            JSON.stringify(Object.assign({}, args, { promiseId }))
+          );
+          const buf = Deno.core.dispatch(id, argsUi8, zeroCopy);
+          if (buf != null) {
+            // Sync result.
+            resolve(decode(buf, name));
+          } else {
+            promiseTable.set(promiseId, { resolve, reject });
+            pendingByOp.get(id).push(promiseId);
+          }
+        });
+        return unwrapResponse(res);
+      }
+    };
+  }
+
+  Deno.jsonOp = jsonOp;
+  Deno.JsonOpError = JsonOpError;
+})();
@@ -0,0 +1,40 @@
+// Calls a guest function for isolateCall, either a global or an export of
+// the already evaluated module `specifier`. JSON arguments arrive as JSON
+// text. Bytes arguments arrive through completion.input() and fill the null
+// placeholders in `args`. Thenable results are awaited before being
+// reported.
+((completion, name, specifier, argsJson, bytesArgs) => {
+  const args = JSON.parse(argsJson);
+  if (bytesArgs.length > 0) {
+    const bytes = completion.input();
+    let offset = 0;
+    for (const { index, byte_length } of bytesArgs) {
+      args[index] = bytes.slice(offset, offset + byte_length);
+      offset += byte_length;
+    }
+  }
+
+  function invoke(target) {
+    const fn = target[name];
+    if (typeof fn !== "function") {
+      throw new TypeError(`${name} is not a function`);
+    }
+    return fn.apply(target, args);
+  }
+
+  function settle(value) {
+    if (value != null && typeof value.then === "function") {
+      value.then(completion.report, completion.reportError);
+    } else {
+      completion.report(value);
+    }
+  }
+
+  if (specifier == null) {
+    settle(invoke(globalThis));
+  } else {
+    import(specifier)
+      .then(invoke)
+      .then(completion.report, completion.reportError);
+  }
+})
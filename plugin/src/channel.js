@@ -0,0 +1,108 @@
+// Guest side of a channel endpoint attached as an op, called with the op
+// name. Defines Deno.channels[name] with postMessage and recv. Messages are
+// sent like completion values: kind 0 for undefined, 1 for bytes and 2 for
+// JSON in UTF-16. A call's control is its command, the message kind and a
+// call id as a little endian u32. Every call completes asynchronously with
+// a tag byte, 0 posted, 1 a message, 2 post on a closed channel and 3 recv
+// on a closed and empty channel, followed by the call id and any message.
+// JSON is encoded with `toUtf16`, see utf16.js.
+((toUtf16, name) => {
+  const opId = Deno.core.ops()[name];
+  // Calls waiting for completion by call id.
+  const pending = new Map();
+  let nextCallId = 0;
+
+  function encode(value) {
+    if (value instanceof ArrayBuffer) {
+      return [1, new Uint8Array(value)];
+    }
+    if (ArrayBuffer.isView(value)) {
+      return [
+        1,
+        new Uint8Array(value.buffer, value.byteOffset, value.byteLength)
+      ];
+    }
+    const json = JSON.stringify(value);
+    if (json === undefined) {
+      return [0, new Uint8Array(0)];
+    }
+    return [2, toUtf16(json)];
+  }
+
+  function decode(message) {
+    const payload = message.slice(1);
+    switch (message[0]) {
+      case 1:
+        return payload;
+      case 2: {
+        const chars = new Uint16Array(payload.buffer);
+        let json = "";
+        for (let i = 0; i < chars.length; i++) {
+          json += String.fromCharCode(chars[i]);
+        }
+        return JSON.parse(json);
+      }
+      default:
+        return undefined;
+    }
+  }
+
+  Deno.core.setAsyncHandler(opId, buf => {
+    const callId = new DataView(buf.buffer, buf.byteOffset).getUint32(1, true);
+    const call = pending.get(callId);
+    if (!call) {
+      return;
+    }
+    pending.delete(callId);
+    switch (buf[0]) {
+      case 0:
+        call.resolve();
+        break;
+      case 1:
+        call.resolve({ done: false, value: decode(buf.slice(5)) });
+        break;
+      case 2:
+        call.reject(new Error(`channel "${name}" is closed`));
+        break;
+      case 3:
+        call.resolve({ done: true, value: undefined });
+        break;
+    }
+  });
+
+  function call(command, kind, data) {
+    return new Promise((resolve, reject) => {
+      const callId = nextCallId;
+      nextCallId = (nextCallId + 1) >>> 0;
+      const control = new Uint8Array(6);
+      control[0] = command;
+      control[1] = kind;
+      new DataView(control.buffer).setUint32(2, callId, true);
+      pending.set(callId, { resolve, reject });
+      // Only a detached endpoint, whose op answers with a NotFound
+      // envelope, responds synchronously.
+      if (Deno.core.dispatch(opId, control, data)) {
+        pending.delete(callId);
+        reject(new Error(`channel "${name}" is detached`));
+      }
+    });
+  }
+
+  const channels = Deno.channels || (Deno.channels = {});
+  channels[name] = {
+    // Resolves once the message is queued, which on a full bounded channel
+    // waits for a receiver to make room. Messages are queued in the order
+    // they were posted.
+    postMessage(value) {
+      const [kind, data] = encode(value);
+      return call(0, kind, data);
+    },
+
+    // Resolves with { done: false, value } for the next message, or with
+    // { done: true } once the channel is closed and drained, so posting
+    // undefined can't be mistaken for the end of the channel.
+    recv() {
+      return call(1, 0);
+    }
+  };
+})
@@ -0,0 +1,291 @@
+use crate::dispatch::Dispatcher;
+use crate::msg::ResourceIdResponse;
+use crate::resources::add_resource;
+use crate::resources::take_resource;
+use crate::resources::Resource;
+use crate::util::UTF16_SHIM;
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use deno_dispatch_json::JsonOp;
+use futures::future::FutureExt;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+const CHANNEL_SHIM: &str = include_str!("channel.js");
+
+const COMMAND_POST: u8 = 0;
+const COMMAND_RECV: u8 = 1;
+
+const TAG_POSTED: u8 = 0;
+const TAG_MESSAGE: u8 = 1;
+const TAG_POST_CLOSED: u8 = 2;
+const TAG_RECV_CLOSED: u8 = 3;
+
+/// A message as sent by the guest, its kind byte followed by the payload.
+type Message = Vec<u8>;
+
+#[derive(Default)]
+struct ChannelState {
+    queue: VecDeque<Message>,
+    // Posts waiting for room in a bounded queue, oldest first, by post id.
+    blocked: VecDeque<(u64, Message)>,
+    next_post_id: u64,
+    receivers: Vec<Waker>,
+    senders: HashMap<u64, Waker>,
+    closed: bool,
+}
+
+impl ChannelState {
+    fn blocked_index(&self, id: u64) -> Option<usize> {
+        self.blocked
+            .iter()
+            .position(|(blocked_id, _)| *blocked_id == id)
+    }
+}
+
+/// A queue shared by every isolate it is attached to. Any endpoint can post
+/// and receive, each message goes to exactly one receiver. Messages are
+/// moved between worker threads without going through the host's JS.
+pub struct Channel {
+    capacity: Option<usize>,
+    state: Mutex<ChannelState>,
+}
+
+impl Resource for Arc<Channel> {
+    const KIND: &'static str = "channel";
+}
+
+fn wake_all(wakers: &mut Vec<Waker>) {
+    for waker in wakers.drain(..) {
+        waker.wake();
+    }
+}
+
+/// Response to an endpoint op call: the tag, the call id the guest passed
+/// and any payload.
+fn response(tag: u8, call_id: &[u8], payload: &[u8]) -> Buf {
+    let mut response = vec![tag];
+    response.extend_from_slice(call_id);
+    response.extend_from_slice(payload);
+    response.into_boxed_slice()
+}
+
+/// Result of `Channel::post`.
+enum Post {
+    Done(u8),
+    Blocked(u64),
+}
+
+impl Channel {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(ChannelState::default()),
+        }
+    }
+
+    /// Queue `message` behind any posts still waiting for room, so messages
+    /// keep the order they were posted in.
+    fn post(&self, message: Message) -> Post {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Post::Done(TAG_POST_CLOSED);
+        }
+        let id = state.next_post_id;
+        state.next_post_id += 1;
+        state.blocked.push_back((id, message));
+        self.admit(&mut state);
+        if state.blocked_index(id).is_some() {
+            Post::Blocked(id)
+        } else {
+            Post::Done(TAG_POSTED)
+        }
+    }
+
+    fn has_room(&self, state: &ChannelState) -> bool {
+        match self.capacity {
+            Some(capacity) => state.queue.len() < capacity,
+            None => true,
+        }
+    }
+
+    /// Move blocked posts into the queue, oldest first, while there is room.
+    fn admit(&self, state: &mut ChannelState) {
+        while self.has_room(state) {
+            let (id, message) = match state.blocked.pop_front() {
+                Some(post) => post,
+                None => break,
+            };
+            state.queue.push_back(message);
+            if let Some(waker) = state.senders.remove(&id) {
+                waker.wake();
+            }
+            wake_all(&mut state.receivers);
+        }
+    }
+
+    /// Fail pending and future posts. Receivers get the messages already
+    /// queued, then see the channel as closed.
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        wake_all(&mut state.receivers);
+        for (_, waker) in state.senders.drain() {
+            waker.wake();
+        }
+    }
+}
+
+/// A post waiting in `ChannelState::blocked`.
+struct SendWorker {
+    channel: Arc<Channel>,
+    id: u64,
+}
+
+impl Future for SendWorker {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.channel.state.lock().unwrap();
+        match state.blocked_index(self.id) {
+            None => Poll::Ready(TAG_POSTED),
+            Some(index) if state.closed => {
+                state.blocked.remove(index);
+                Poll::Ready(TAG_POST_CLOSED)
+            }
+            Some(_) => {
+                state.senders.insert(self.id, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for SendWorker {
+    // The posting isolate is gone, its message must not be delivered later.
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().unwrap();
+        let id = self.id;
+        state.blocked.retain(|(blocked_id, _)| *blocked_id != id);
+        state.senders.remove(&id);
+    }
+}
+
+struct RecvWorker {
+    channel: Arc<Channel>,
+}
+
+impl Future for RecvWorker {
+    type Output = Result<Message, ()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let channel = &self.channel;
+        let mut state = channel.state.lock().unwrap();
+        if let Some(message) = state.queue.pop_front() {
+            channel.admit(&mut state);
+            return Poll::Ready(Ok(message));
+        }
+        if state.closed {
+            return Poll::Ready(Err(()));
+        }
+        state.receivers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// One isolate's end of a channel, registered as an op in that isolate.
+/// See channel.js for the wire format.
+pub struct ChannelEndpoint {
+    channel: Arc<Channel>,
+}
+
+impl ChannelEndpoint {
+    pub fn new(channel: Arc<Channel>) -> Self {
+        Self { channel }
+    }
+}
+
+impl Dispatcher for ChannelEndpoint {
+    fn dispatch(&self, data: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp {
+        let channel = self.channel.clone();
+        let call_id = data.get(2..6).unwrap_or(&[0; 4]).to_vec();
+        match data.first() {
+            Some(&COMMAND_POST) => {
+                let mut message = vec![data.get(1).copied().unwrap_or(0)];
+                if let Some(buf) = zero_copy {
+                    message.extend_from_slice(&buf);
+                }
+                let tag = match channel.post(message) {
+                    Post::Done(tag) => futures::future::ready(tag).left_future(),
+                    Post::Blocked(id) => SendWorker { channel, id }.right_future(),
+                };
+                let fut = tag.map(move |tag| Ok(response(tag, &call_id, &[])));
+                Op::Async(fut.boxed())
+            }
+            Some(&COMMAND_RECV) => {
+                let fut = RecvWorker { channel }.map(move |result| {
+                    Ok(match result {
+                        Ok(message) => response(TAG_MESSAGE, &call_id, &message),
+                        Err(()) => response(TAG_RECV_CLOSED, &call_id, &[]),
+                    })
+                });
+                Op::Async(fut.boxed())
+            }
+            _ => Op::Sync(Vec::new().into_boxed_slice()),
+        }
+    }
+}
+
+/// Build a script that defines `Deno.channels[name]` for the endpoint
+/// registered as op `name`, see channel.js.
+pub fn wrap_channel(name: &str) -> String {
+    format!(
+        "{}({}, {});",
+        CHANNEL_SHIM.trim_end(),
+        UTF16_SHIM.trim_end(),
+        json!(name)
+    )
+}
+
+#[derive(Deserialize)]
+struct NewChannelOptions {
+    pub capacity: Option<usize>,
+}
+
+pub fn op_new_channel(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: NewChannelOptions = serde_json::from_value(args)?;
+
+    if args.capacity == Some(0) {
+        return Err(ErrBox::from(JsonError::new(
+            "InvalidInput",
+            "channel capacity must be at least 1".to_string(),
+        )));
+    }
+    let rid = add_resource(Arc::new(Channel::new(args.capacity)))?;
+    Ok(JsonOp::Sync(json!(ResourceIdResponse { rid })))
+}
+
+#[derive(Deserialize)]
+struct ChannelCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_channel_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: ChannelCloseOptions = serde_json::from_value(args)?;
+
+    // Endpoints keep the channel itself alive until they are unregistered.
+    let channel = take_resource::<Arc<Channel>>(args.rid)?;
+    channel.close();
+
+    Ok(JsonOp::Sync(json!({})))
+}
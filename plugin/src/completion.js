@@ -0,0 +1,56 @@
+// Reports guest values back to the plugin through the internal completion
+// op. Bytes are passed as is, every other value as JSON encoded with
+// `toUtf16`, see utf16.js. Every dispatch carries `token`, the plugin
+// ignores reports and input requests without the current one.
+((toUtf16, token) => {
+  const opId = Deno.core.ops()["__completion"];
+  const inputOpId = Deno.core.ops()["__input"];
+
+  // The kind of value in the first byte, the token in the next four.
+  function control(kind) {
+    const buf = new Uint8Array(5);
+    buf[0] = kind;
+    new DataView(buf.buffer).setUint32(1, token, true);
+    return buf;
+  }
+
+  function sendJson(kind, value) {
+    const json = JSON.stringify(value);
+    if (json === undefined) {
+      Deno.core.dispatch(opId, control(0));
+      return;
+    }
+    Deno.core.dispatch(opId, control(kind), toUtf16(json));
+  }
+
+  function report(value) {
+    if (value instanceof ArrayBuffer) {
+      Deno.core.dispatch(opId, control(1), new Uint8Array(value));
+    } else if (ArrayBuffer.isView(value)) {
+      const bytes = new Uint8Array(
+        value.buffer,
+        value.byteOffset,
+        value.byteLength
+      );
+      Deno.core.dispatch(opId, control(1), bytes);
+    } else {
+      sendJson(2, value);
+    }
+  }
+
+  // Used for rejected promises, which never reach the host as a JSError.
+  function reportError(err) {
+    const isError = err instanceof Error;
+    sendJson(3, {
+      name: isError ? err.name : "Error",
+      message: isError ? err.message : String(err)
+    });
+  }
+
+  // Bytes the host handed to this shim.
+  function input() {
+    return Deno.core.dispatch(inputOpId, control(0));
+  }
+
+  return { report, reportError, input };
+})
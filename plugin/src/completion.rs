@@ -0,0 +1,247 @@
+use crate::errors::guest_rejection;
+use crate::resources::bad_resource;
+use crate::util::utf16_units;
+use crate::util::UTF16_SHIM;
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+/// Name of the internal op guest shims use to hand values back to the host.
+pub const COMPLETION_OP_NAME: &str = "__completion";
+
+/// Name of the internal op guest shims use to fetch bytes from the host.
+pub const INPUT_OP_NAME: &str = "__input";
+
+const COMPLETION_SHIM: &str = include_str!("completion.js");
+
+const CALL_SHIM: &str = include_str!("call.js");
+
+const NAMESPACE_SHIM: &str = include_str!("namespace.js");
+
+const GLOBAL_SHIM: &str = include_str!("global.js");
+
+const KIND_UNDEFINED: u8 = 0;
+const KIND_BYTES: u8 = 1;
+const KIND_JSON: u8 = 2;
+const KIND_ERROR: u8 = 3;
+
+/// A guest value as reported to the host. Bytes are kept on the plugin side
+/// until the host copies them out with isolateReadBytes.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CompletionValue {
+    Undefined,
+    Json { value: Value },
+    Bytes { id: u32, byte_length: usize },
+}
+
+/// Position and size of a bytes argument to isolateCall.
+#[derive(Deserialize, Serialize)]
+pub struct BytesArg {
+    pub index: usize,
+    pub byte_length: usize,
+}
+
+/// Values guest shims report to the host. Guest code can dispatch the
+/// internal ops too, so each shim gets a fresh token from `begin` and
+/// reports or input requests without the current token are ignored.
+#[derive(Default)]
+pub struct Completions {
+    token: Mutex<Option<u32>>,
+    last: Mutex<Option<(u8, Vec<u8>)>>,
+    next_bytes_id: AtomicU32,
+    bytes: Mutex<HashMap<u32, Vec<u8>>>,
+    input: Mutex<Option<Vec<u8>>>,
+}
+
+impl Completions {
+    /// Start a shim run: forget anything recorded before, hold `input` for
+    /// the shim to fetch and return the token the shim must pass along.
+    pub fn begin(&self, input: Option<Vec<u8>>) -> u32 {
+        // RandomState is seeded randomly, so guest code can't guess tokens.
+        let token = RandomState::new().build_hasher().finish() as u32;
+        *self.token.lock().unwrap() = Some(token);
+        *self.last.lock().unwrap() = None;
+        *self.input.lock().unwrap() = input;
+        token
+    }
+
+    /// End a shim run that reports nothing, see `begin`.
+    pub fn end(&self) {
+        *self.token.lock().unwrap() = None;
+        *self.input.lock().unwrap() = None;
+    }
+
+    /// Whether `control` carries the current token after its first byte.
+    fn accepts(&self, control: &[u8]) -> bool {
+        let token = match control.get(1..5) {
+            Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            None => return false,
+        };
+        *self.token.lock().unwrap() == Some(token)
+    }
+
+    /// Handler for the COMPLETION_OP_NAME op. Each token is good for one
+    /// report.
+    pub fn record(&self, control: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp {
+        if self.accepts(control) {
+            *self.token.lock().unwrap() = None;
+            let kind = control[0];
+            let data = zero_copy.map(|buf| buf.to_vec()).unwrap_or_default();
+            *self.last.lock().unwrap() = Some((kind, data));
+        }
+        Op::Sync(Vec::new().into_boxed_slice())
+    }
+
+    /// Whether a value was recorded since the last `take`.
+    pub fn is_recorded(&self) -> bool {
+        self.last.lock().unwrap().is_some()
+    }
+
+    /// Handler for the INPUT_OP_NAME op.
+    pub fn take_input(&self, control: &[u8]) -> CoreOp {
+        let input = if self.accepts(control) {
+            self.input.lock().unwrap().take()
+        } else {
+            None
+        };
+        Op::Sync(input.unwrap_or_default().into_boxed_slice())
+    }
+
+    /// Take the value recorded since the last call.
+    pub fn take(&self) -> Result<CompletionValue, ErrBox> {
+        self.end();
+        let (kind, data) = self
+            .last
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or((KIND_UNDEFINED, Vec::new()));
+        match kind {
+            KIND_JSON => Ok(CompletionValue::Json {
+                value: decode_json(&data)?,
+            }),
+            KIND_ERROR => Err(guest_rejection(decode_json(&data)?)),
+            KIND_BYTES => {
+                let byte_length = data.len();
+                let id = self.next_bytes_id.fetch_add(1, Ordering::SeqCst);
+                if byte_length > 0 {
+                    self.bytes.lock().unwrap().insert(id, data);
+                }
+                Ok(CompletionValue::Bytes { id, byte_length })
+            }
+            _ => Ok(CompletionValue::Undefined),
+        }
+    }
+
+    pub fn read_bytes(&self, id: u32, out: &mut [u8]) -> Result<(), ErrBox> {
+        let mut bytes = self.bytes.lock().unwrap();
+        let len = bytes
+            .get(&id)
+            .ok_or_else(|| bad_resource("completion bytes", id))?
+            .len();
+        if out.len() < len {
+            return Err(ErrBox::from(JsonError::new(
+                "InvalidInput",
+                format!("completion needs a {} byte buffer, got {}", len, out.len()),
+            )));
+        }
+        let data = bytes.remove(&id).unwrap();
+        out[..len].copy_from_slice(&data);
+        Ok(())
+    }
+}
+
+/// JSON as sent by completion.js, encoded with UTF16_SHIM.
+fn decode_json(data: &[u8]) -> Result<Value, ErrBox> {
+    let json = String::from_utf16(&utf16_units(data))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// The completion object shims report through, see completion.js.
+fn completion(token: u32) -> String {
+    format!(
+        "{}({}, {})",
+        COMPLETION_SHIM.trim_end(),
+        UTF16_SHIM.trim_end(),
+        token
+    )
+}
+
+/// Wrap `source` so running it reports its completion value. `token` comes
+/// from `Completions::begin`. deno_core drops a script's completion value,
+/// so `source` runs through an indirect eval and gets eval scoping, see
+/// ExecuteOptions.returnValue in isolate.ts.
+pub fn wrap_source(token: u32, source: &str, filename: &str) -> String {
+    format!(
+        "{}.report((0, eval)({}));",
+        completion(token),
+        Value::from(format!("{}\n//# sourceURL={}", source, filename))
+    )
+}
+
+/// Build a script that calls the guest function `name` and reports its
+/// result, see call.js. `args` go in as JSON text for the guest to parse,
+/// a JSON literal pasted into the script would treat a `__proto__` key as
+/// the prototype.
+pub fn wrap_call(
+    token: u32,
+    name: &str,
+    module_specifier: Option<&str>,
+    args: &[Value],
+    bytes_args: &[BytesArg],
+) -> String {
+    format!(
+        "{}({}, {}, {}, {}, {});",
+        CALL_SHIM.trim_end(),
+        completion(token),
+        Value::from(name),
+        json!(module_specifier),
+        Value::from(json!(args).to_string()),
+        json!(bytes_args)
+    )
+}
+
+/// Build a script that reports the exports of `module_specifier`, see
+/// namespace.js.
+pub fn wrap_namespace(token: u32, module_specifier: &str) -> String {
+    format!(
+        "{}({}, {});",
+        NAMESPACE_SHIM.trim_end(),
+        completion(token),
+        json!(module_specifier)
+    )
+}
+
+/// Build a script that defines the global `name`, see global.js. With
+/// `is_bytes` set the value is read from the input op instead of `value`.
+/// `value` goes in as JSON text, see `wrap_call`.
+pub fn wrap_set_global(
+    token: u32,
+    name: &str,
+    value: &Value,
+    is_bytes: bool,
+    read_only: bool,
+    freeze: bool,
+) -> String {
+    format!(
+        "{}({}, {}, {}, {}, {}, {});",
+        GLOBAL_SHIM.trim_end(),
+        completion(token),
+        json!(name),
+        Value::from(value.to_string()),
+        is_bytes,
+        read_only,
+        freeze
+    )
+}
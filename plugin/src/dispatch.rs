@@ -1,5 +1,12 @@
 use crate::msg::ResourceId;
+use crate::resources::add_resource;
+use crate::resources::bad_resource;
+use crate::resources::get_resource;
+use crate::resources::take_resource;
+use crate::resources::Resource;
+use crate::util::park_on;
 use deno_core::*;
+use deno_dispatch_json::JsonError;
 use deno_dispatch_json::JsonOp;
 use futures::channel::oneshot;
 use futures::future::FutureExt;
@@ -12,6 +19,7 @@ use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -20,39 +28,55 @@ use std::sync::RwLock;
 use std::task::Context;
 use std::task::Poll;
 
-lazy_static! {
-    static ref NEXT_DISPATCHER_ID: AtomicU32 = AtomicU32::new(1);
-    static ref DISPATCHER_MAP: RwLock<HashMap<u32, Arc<Box<dyn Dispatcher>>>> =
-        RwLock::new(HashMap::new());
-    static ref NEXT_STD_DISPATCHER_ID: AtomicU32 = AtomicU32::new(1);
-    static ref STD_DISPATCHER_MAP: RwLock<HashMap<u32, Arc<StdDispatcher>>> =
-        RwLock::new(HashMap::new());
-}
-
 // TODO(afinch7) maybe move this to another package/crate
 pub trait Dispatcher: Send + Sync {
     fn dispatch(&self, data: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp;
 }
 
-pub fn insert_dispatcher(dispatcher: Arc<Box<dyn Dispatcher>>) -> ResourceId {
-    let rid = NEXT_DISPATCHER_ID.fetch_add(1, Ordering::SeqCst);
-    let mut lock = DISPATCHER_MAP.write().unwrap();
-    lock.insert(rid, dispatcher);
-    rid
+impl Resource for Arc<Box<dyn Dispatcher>> {
+    const KIND: &'static str = "dispatcher";
+}
+
+pub fn insert_dispatcher(dispatcher: Arc<Box<dyn Dispatcher>>) -> Result<ResourceId, ErrBox> {
+    add_resource(dispatcher)
+}
+
+pub fn get_dispatcher(dispatcher_rid: ResourceId) -> Result<Arc<Box<dyn Dispatcher>>, ErrBox> {
+    get_resource(dispatcher_rid)
+}
+
+#[derive(Deserialize)]
+struct DispatcherCloseOptions {
+    pub rid: u32,
 }
 
-pub fn get_dispatcher(dispatcher_rid: ResourceId) -> Arc<Box<dyn Dispatcher>> {
-    let lock = DISPATCHER_MAP.read().unwrap();
-    let dispatcher_ref = lock.get(&dispatcher_rid).unwrap();
-    dispatcher_ref.clone()
+pub fn op_dispatcher_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: DispatcherCloseOptions = serde_json::from_value(args)?;
+    take_resource::<Arc<Box<dyn Dispatcher>>>(args.rid)?;
+    Ok(JsonOp::Sync(json!({})))
 }
 
 pub type InsertDispatcherAccessor = fn(Arc<Box<dyn Dispatcher>>) -> ResourceId;
 pub type GetDispatcherAccessor = fn(ResourceId) -> Arc<Box<dyn Dispatcher>>;
+pub type TryGetDispatcherAccessor = fn(ResourceId) -> Result<Arc<Box<dyn Dispatcher>>, ErrBox>;
+
+/// Backs `get_dispatcher_ptr`, which other plugins already call through a
+/// `GetDispatcherAccessor`, so a bad rid still panics here. New callers
+/// should use `try_get_dispatcher_ptr` instead.
+fn get_dispatcher_unchecked(dispatcher_rid: ResourceId) -> Arc<Box<dyn Dispatcher>> {
+    get_dispatcher(dispatcher_rid).unwrap()
+}
+
+/// Backs `insert_dispatcher_ptr`, which panics like it always has if the
+/// resource table is full.
+fn insert_dispatcher_unchecked(dispatcher: Arc<Box<dyn Dispatcher>>) -> ResourceId {
+    insert_dispatcher(dispatcher).unwrap()
+}
 
 #[derive(Serialize)]
 struct GetDispatcherAccessorPtrResponse {
     pub get_dispatcher_ptr: usize,
+    pub try_get_dispatcher_ptr: usize,
     pub insert_dispatcher_ptr: usize,
 }
 
@@ -60,65 +84,137 @@ pub fn op_get_dispatcher_accessor_ptrs(
     _args: Value,
     _zero_copy: Option<PinnedBuf>,
 ) -> Result<JsonOp, ErrBox> {
-    let get_dispatcher_ptr: usize =
-        &(get_dispatcher as GetDispatcherAccessor) as *const GetDispatcherAccessor as usize;
-    let insert_dispatcher_ptr: usize = &(insert_dispatcher as InsertDispatcherAccessor)
+    let get_dispatcher_ptr: usize = &(get_dispatcher_unchecked as GetDispatcherAccessor)
+        as *const GetDispatcherAccessor as usize;
+    let try_get_dispatcher_ptr: usize =
+        &(get_dispatcher as TryGetDispatcherAccessor) as *const TryGetDispatcherAccessor as usize;
+    let insert_dispatcher_ptr: usize = &(insert_dispatcher_unchecked as InsertDispatcherAccessor)
         as *const InsertDispatcherAccessor as usize;
     Ok(JsonOp::Sync(json!(GetDispatcherAccessorPtrResponse {
         get_dispatcher_ptr,
+        try_get_dispatcher_ptr,
         insert_dispatcher_ptr,
     })))
 }
 
 type StdDispatchReq = (u32, Vec<u8>, Option<Vec<u8>>);
 type StdDispatchReqQueue = VecDeque<StdDispatchReq>;
+type StdDispatchReqPayload = (Vec<u8>, Option<Vec<u8>>);
+
+/// Guest ops still in flight when a StdDispatcher is closed resolve with an
+/// empty buffer, since raw ops have no error channel to reject through.
+fn closed_response() -> Buf {
+    Vec::new().into_boxed_slice()
+}
+
+pub(crate) fn missing_buffer_error(op_name: &str) -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "InvalidInput",
+        format!("{} needs a buffer", op_name),
+    ))
+}
 
 struct StdDispatcher {
+    pub sync_only: bool,
+    pub closed: AtomicBool,
     pub next_cmd_id: AtomicU32,
     pub res_senders: Arc<RwLock<HashMap<u32, oneshot::Sender<CoreOp>>>>,
+    pub async_res_senders: Arc<RwLock<HashMap<u32, oneshot::Sender<Buf>>>>,
     pub req_queue: Arc<Mutex<StdDispatchReqQueue>>,
+    pub req_payloads: Arc<Mutex<HashMap<u32, StdDispatchReqPayload>>>,
     pub waker: AtomicWaker,
 }
 
 impl StdDispatcher {
-    pub fn new() -> Self {
+    pub fn new(sync_only: bool) -> Self {
         Self {
+            sync_only,
+            closed: AtomicBool::new(false),
             next_cmd_id: AtomicU32::new(0),
             res_senders: Arc::new(RwLock::new(HashMap::new())),
+            async_res_senders: Arc::new(RwLock::new(HashMap::new())),
             req_queue: Arc::new(Mutex::new(VecDeque::new())),
+            req_payloads: Arc::new(Mutex::new(HashMap::new())),
             waker: AtomicWaker::new(),
         }
     }
+
+    /// Answer to a dispatch after close, in the same shape a live response
+    /// would have. An empty sync response reaches the guest as no response
+    /// at all, which to a guest expecting an async one means still pending.
+    fn closed_op(&self) -> CoreOp {
+        if self.sync_only {
+            Op::Sync(closed_response())
+        } else {
+            Op::Async(futures::future::ok(closed_response()).boxed())
+        }
+    }
+
+    pub fn close(&self) {
+        {
+            let mut lock = self.res_senders.write().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+            // Dropping the senders resolves every pending guest op.
+            lock.clear();
+            self.async_res_senders.write().unwrap().clear();
+            self.req_queue.lock().unwrap().clear();
+            self.req_payloads.lock().unwrap().clear();
+        }
+        self.waker.wake();
+    }
 }
 
 impl Dispatcher for StdDispatcher {
     fn dispatch(&self, data: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp {
         let cmd_id = self.next_cmd_id.fetch_add(1, Ordering::SeqCst);
-        let (res_sender, mut res_reciever) = oneshot::channel::<CoreOp>();
+        let (res_sender, res_reciever) = oneshot::channel::<CoreOp>();
         {
             let mut lock = self.res_senders.write().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return self.closed_op();
+            }
             lock.insert(cmd_id, res_sender);
             let mut queue = self.req_queue.lock().unwrap();
             queue.push_back((cmd_id, data.to_vec(), zero_copy.map(|v| v.to_vec())));
         }
         self.waker.wake();
-        // TODO(afinch7) This is a realy ugly hack. Find a better solution here.
-        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
-        loop {
-            match res_reciever.poll_unpin(&mut cx) {
-                Poll::Ready(v) => return v.unwrap(),
-                Poll::Pending => {}
-            };
+        if self.sync_only {
+            // Blocks the calling isolate thread until the host responds, so
+            // this must never be used when host and guest share a thread.
+            match park_on(res_reciever) {
+                Ok(Op::Sync(buf)) => Op::Sync(buf),
+                Ok(Op::Async(fut)) => {
+                    Op::Sync(park_on(fut).unwrap_or_else(|_| closed_response()))
+                }
+                Err(_) => Op::Sync(closed_response()),
+            }
+        } else {
+            let fut = res_reciever.then(|res| match res {
+                Ok(Op::Sync(buf)) => futures::future::ok(buf).boxed(),
+                Ok(Op::Async(fut)) => fut,
+                Err(_) => futures::future::ok(closed_response()).boxed(),
+            });
+            Op::Async(fut.boxed())
         }
     }
 }
 
+impl Resource for Arc<StdDispatcher> {
+    const KIND: &'static str = "stdDispatcher";
+}
+
 impl Dispatcher for Arc<StdDispatcher> {
     fn dispatch(&self, data: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp {
         self.as_ref().dispatch(data, zero_copy)
     }
 }
 
+#[derive(Deserialize)]
+struct NewStdDispatcherOptions {
+    #[serde(default)]
+    pub sync_only: bool,
+}
+
 #[derive(Serialize)]
 struct NewStdDispatcherResponse {
     pub std_dispatcher_rid: u32,
@@ -126,14 +222,13 @@ struct NewStdDispatcherResponse {
 }
 
 pub fn op_new_std_dispatcher(
-    _args: Value,
+    args: Value,
     _zero_copy: Option<PinnedBuf>,
 ) -> Result<JsonOp, ErrBox> {
-    let std_rid = NEXT_STD_DISPATCHER_ID.fetch_add(1, Ordering::SeqCst);
-    let dispatcher = Arc::new(StdDispatcher::new());
-    let mut lock = STD_DISPATCHER_MAP.write().unwrap();
-    lock.insert(std_rid, dispatcher.clone());
-    let rid = insert_dispatcher(Arc::new(Box::new(dispatcher) as Box<dyn Dispatcher>));
+    let args: NewStdDispatcherOptions = serde_json::from_value(args)?;
+    let dispatcher = Arc::new(StdDispatcher::new(args.sync_only));
+    let std_rid = add_resource(dispatcher.clone())?;
+    let rid = insert_dispatcher(Arc::new(Box::new(dispatcher) as Box<dyn Dispatcher>))?;
 
     Ok(JsonOp::Sync(json!(NewStdDispatcherResponse {
         std_dispatcher_rid: std_rid,
@@ -146,34 +241,40 @@ struct StdDispatcherWaitForDispatchOptions {
     pub rid: u32,
 }
 
+/// Header for a pending dispatch. The payload itself is copied into a host
+/// buffer by stdDispatcherReadRequest, data first followed by zero_copy.
 #[derive(Serialize)]
 struct StdDispatcherWaitForDispatchResponse {
     pub cmd_id: u32,
-    // TODO(afinch7) encode these outside of json.
-    // Currently we encode data and zero_copy into json.
-    // This will be very slow, but it works for testing.
-    pub data: Vec<u8>,
-    pub zero_copy: Option<Vec<u8>>,
+    pub data_len: usize,
+    pub zero_copy_len: Option<usize>,
 }
 
 struct RecvWorker {
-    pub rid: u32,
+    pub dispatcher: Arc<StdDispatcher>,
 }
 
 impl Future for RecvWorker {
     type Output = Result<Value, ErrBox>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let lock = STD_DISPATCHER_MAP.read().unwrap();
-        let dispatcher = lock.get(&self.rid).unwrap();
+        let dispatcher = &self.dispatcher;
         dispatcher.waker.register(cx.waker());
+        if dispatcher.closed.load(Ordering::SeqCst) {
+            return Poll::Ready(Ok(json!({ "closed": true })));
+        }
         let mut queue = dispatcher.req_queue.lock().unwrap();
         let result = match queue.pop_front() {
-            Some(req) => Poll::Ready(Ok(json!(StdDispatcherWaitForDispatchResponse {
-                cmd_id: req.0,
-                data: req.1,
-                zero_copy: req.2,
-            }))),
+            Some((cmd_id, data, zero_copy)) => {
+                let response = StdDispatcherWaitForDispatchResponse {
+                    cmd_id,
+                    data_len: data.len(),
+                    zero_copy_len: zero_copy.as_ref().map(|v| v.len()),
+                };
+                let mut payloads = dispatcher.req_payloads.lock().unwrap();
+                payloads.insert(cmd_id, (data, zero_copy));
+                Poll::Ready(Ok(json!(response)))
+            }
             None => Poll::Pending,
         };
         result
@@ -186,11 +287,47 @@ pub fn op_std_dispatcher_wait_for_dispatch(
 ) -> Result<JsonOp, ErrBox> {
     let args: StdDispatcherWaitForDispatchOptions = serde_json::from_value(args)?;
 
-    let op = RecvWorker { rid: args.rid };
+    let dispatcher = get_resource::<Arc<StdDispatcher>>(args.rid)?;
+
+    let op = RecvWorker { dispatcher };
 
     Ok(JsonOp::Async(op.boxed()))
 }
 
+#[derive(Deserialize)]
+struct StdDispatcherReadRequestOptions {
+    pub rid: u32,
+    pub cmd_id: u32,
+}
+
+pub fn op_std_dispatcher_read_request(
+    args: Value,
+    zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: StdDispatcherReadRequestOptions = serde_json::from_value(args)?;
+    let mut out = zero_copy.ok_or_else(|| missing_buffer_error("stdDispatcherReadRequest"))?;
+    let dispatcher = get_resource::<Arc<StdDispatcher>>(args.rid)?;
+    let mut payloads = dispatcher.req_payloads.lock().unwrap();
+    let (data, zero_copy) = payloads
+        .get(&args.cmd_id)
+        .ok_or_else(|| bad_resource("dispatch request", args.cmd_id))?;
+    // Checked before taking the payload, so the host can retry.
+    let len = data.len() + zero_copy.as_ref().map_or(0, |v| v.len());
+    if out.len() < len {
+        return Err(ErrBox::from(JsonError::new(
+            "InvalidInput",
+            format!("request needs a {} byte buffer, got {}", len, out.len()),
+        )));
+    }
+    let (data, zero_copy) = payloads.remove(&args.cmd_id).unwrap();
+    let data_len = data.len();
+    out[..data_len].copy_from_slice(&data);
+    if let Some(zero_copy) = zero_copy {
+        out[data_len..data_len + zero_copy.len()].copy_from_slice(&zero_copy);
+    }
+    Ok(JsonOp::Sync(json!({})))
+}
+
 #[derive(Deserialize)]
 struct StdDispatcherRespondOptions {
     pub rid: u32,
@@ -202,18 +339,75 @@ pub fn op_std_dispatcher_respond(
     zero_copy: Option<PinnedBuf>,
 ) -> Result<JsonOp, ErrBox> {
     let args: StdDispatcherRespondOptions = serde_json::from_value(args)?;
-    let lock = STD_DISPATCHER_MAP.read().unwrap();
-    let dispatcher = lock.get(&args.rid).unwrap();
+    let dispatcher = get_resource::<Arc<StdDispatcher>>(args.rid)?;
     let mut senders_lock = dispatcher.res_senders.write().unwrap();
-    let sender = senders_lock.remove(&args.cmd_id).unwrap();
+    let sender = senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("dispatch request", args.cmd_id))?;
     match zero_copy {
         Some(buf) => {
-            assert!(sender.send(Op::Sync(buf[..].into())).is_ok());
+            // The isolate may have been closed while the op was pending.
+            let _ = sender.send(Op::Sync(buf[..].into()));
             Ok(JsonOp::Sync(json!({})))
         }
         None => {
-            panic!("Promise returns not implemented yet!");
-            // TODO(afinch7) implement promise returns.
+            // No buffer means the host will respond later with
+            // stdDispatcherRespondAsync, so hand the guest a pending op.
+            let (async_sender, async_reciever) = oneshot::channel::<Buf>();
+            // Registered even if the isolate is gone, so the host's later
+            // stdDispatcherRespondAsync still finds it.
+            let mut async_senders_lock = dispatcher.async_res_senders.write().unwrap();
+            async_senders_lock.insert(args.cmd_id, async_sender);
+            let fut = async_reciever
+                .map(|res| Ok::<Buf, ()>(res.unwrap_or_else(|_| closed_response())));
+            let _ = sender.send(Op::Async(fut.boxed()));
+            Ok(JsonOp::Sync(json!({})))
         }
     }
 }
+
+#[derive(Deserialize)]
+struct StdDispatcherRespondAsyncOptions {
+    pub rid: u32,
+    pub cmd_id: u32,
+    // Resolve the guest op with an empty buffer, like a closed dispatcher,
+    // for hosts that failed to produce a response.
+    #[serde(default)]
+    pub closed: bool,
+}
+
+pub fn op_std_dispatcher_respond_async(
+    args: Value,
+    zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: StdDispatcherRespondAsyncOptions = serde_json::from_value(args)?;
+    if !args.closed && zero_copy.is_none() {
+        return Err(missing_buffer_error("stdDispatcherRespondAsync"));
+    }
+    let dispatcher = get_resource::<Arc<StdDispatcher>>(args.rid)?;
+    let mut async_senders_lock = dispatcher.async_res_senders.write().unwrap();
+    let sender = async_senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("dispatch request", args.cmd_id))?;
+    let response = match zero_copy {
+        Some(buf) if !args.closed => buf[..].into(),
+        _ => closed_response(),
+    };
+    let _ = sender.send(response);
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+struct StdDispatcherCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_std_dispatcher_close(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: StdDispatcherCloseOptions = serde_json::from_value(args)?;
+    let dispatcher = take_resource::<Arc<StdDispatcher>>(args.rid)?;
+    dispatcher.close();
+    Ok(JsonOp::Sync(json!({})))
+}
@@ -0,0 +1,109 @@
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+#[derive(Serialize)]
+struct GuestStackFrame {
+    pub line_number: i64,
+    pub column: i64,
+    pub script_name: String,
+    pub function_name: String,
+    pub is_eval: bool,
+    pub is_constructor: bool,
+    pub is_wasm: bool,
+}
+
+#[derive(Serialize)]
+struct GuestErrorData {
+    pub message: String,
+    pub name: Option<String>,
+    pub script_resource_name: Option<String>,
+    pub line_number: Option<i64>,
+    pub start_column: Option<i64>,
+    pub end_column: Option<i64>,
+    pub source_line: Option<String>,
+    pub frames: Vec<GuestStackFrame>,
+}
+
+fn js_error_to_json(js_error: &JSError) -> JsonError {
+    // deno_core only hands us V8's message text, e.g. "Uncaught TypeError:
+    // foo". `throw "TypeError: foo"` reads the same, so the name can't be
+    // told from it and is left unknown. A guest wrapper could catch the
+    // exception and report its name, but rethrowing it would replace V8's
+    // location with the wrapper's, so thrown exceptions go without one.
+    let message = js_error.message.trim_start_matches("Uncaught ").to_string();
+    let data = GuestErrorData {
+        message: message.clone(),
+        name: None,
+        script_resource_name: js_error.script_resource_name.clone(),
+        line_number: js_error.line_number,
+        start_column: js_error.start_column,
+        end_column: js_error.end_column,
+        source_line: js_error.source_line.clone(),
+        frames: js_error
+            .frames
+            .iter()
+            .map(|frame| GuestStackFrame {
+                line_number: frame.line_number,
+                column: frame.column,
+                script_name: frame.script_name.clone(),
+                function_name: frame.function_name.clone(),
+                is_eval: frame.is_eval,
+                is_constructor: frame.is_constructor,
+                is_wasm: frame.is_wasm,
+            })
+            .collect(),
+    };
+    JsonError::new("GuestError", message).with_data(json!(data))
+}
+
+/// Turn an exception thrown by guest code into a "GuestError" JsonError that
+/// carries the message, location and stack frames to the host. Any other
+/// error is passed through untouched.
+pub fn guest_error(err: ErrBox) -> ErrBox {
+    match err.downcast_ref::<JSError>() {
+        Some(js_error) => ErrBox::from(js_error_to_json(js_error)),
+        None => err,
+    }
+}
+
+/// Like `guest_error`, but other errors are flattened into a JsonError as
+/// well so the result can be cloned and handed to several waiters.
+pub fn guest_json_error(err: ErrBox) -> JsonError {
+    if let Some(js_error) = err.downcast_ref::<JSError>() {
+        return js_error_to_json(js_error);
+    }
+    match err.downcast_ref::<JsonError>() {
+        Some(json_error) => json_error.clone(),
+        None => JsonError::new("Error", err.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct GuestRejection {
+    pub name: String,
+    pub message: String,
+}
+
+/// Turn a rejection reported by a guest shim into a "GuestError". Unlike
+/// thrown exceptions these carry no location or stack frames.
+pub fn guest_rejection(rejection: Value) -> ErrBox {
+    let rejection: GuestRejection = match serde_json::from_value(rejection) {
+        Ok(rejection) => rejection,
+        Err(err) => return ErrBox::from(err),
+    };
+    let data = GuestErrorData {
+        message: rejection.message.clone(),
+        name: Some(rejection.name),
+        script_resource_name: None,
+        line_number: None,
+        start_column: None,
+        end_column: None,
+        source_line: None,
+        frames: Vec::new(),
+    };
+    ErrBox::from(JsonError::new("GuestError", rejection.message).with_data(json!(data)))
+}
@@ -0,0 +1,23 @@
+// Defines a global for isolateSetGlobal. JSON values arrive as JSON text.
+// Bytes values arrive through completion.input() and are copied so they live
+// on the V8 heap, which keeps them in snapshots.
+((completion, name, json, isBytes, readOnly, freeze) => {
+  let value = JSON.parse(json);
+  if (isBytes) {
+    const input = completion.input();
+    value = new Uint8Array(input || 0);
+  } else if (freeze) {
+    (function deepFreeze(object) {
+      if (object !== null && typeof object === "object") {
+        Object.freeze(object);
+        Object.values(object).forEach(deepFreeze);
+      }
+    })(value);
+  }
+  Object.defineProperty(globalThis, name, {
+    value,
+    enumerable: true,
+    writable: !readOnly,
+    configurable: !readOnly
+  });
+})
@@ -1,31 +1,96 @@
+use crate::channel::wrap_channel;
+use crate::channel::Channel;
+use crate::channel::ChannelEndpoint;
+use crate::completion::wrap_call;
+use crate::completion::wrap_namespace;
+use crate::completion::wrap_set_global;
+use crate::completion::wrap_source;
+use crate::completion::BytesArg;
+use crate::completion::CompletionValue;
+use crate::completion::Completions;
+use crate::completion::COMPLETION_OP_NAME;
+use crate::completion::INPUT_OP_NAME;
 use crate::dispatch::get_dispatcher;
+use crate::dispatch::Dispatcher;
+use crate::errors::guest_error;
 use crate::modules::get_loader;
+use crate::modules::CALL_REFERRER;
+use crate::modules::NAMESPACE_REFERRER;
+use crate::modules::LoadedModules;
 use crate::msg::ResourceId;
 use crate::msg::ResourceIdResponse;
+use crate::op_table::OpTable;
+use crate::output::output_shim;
+use crate::output::Output;
+use crate::output::OutputMode;
+use crate::output::OutputWorker;
+use crate::output::OUTPUT_OP_NAME;
+use crate::resources::add_resource;
+use crate::resources::get_resource;
+use crate::resources::take_resource;
+use crate::resources::Resource;
+use crate::stats::Stats;
+use crate::termination::ArmedWatchdog;
+use crate::termination::Termination;
+use crate::util::park_on;
+use crate::worker::busy_error;
+use crate::worker::Worker;
 use deno_core::*;
+use deno_dispatch_json::JsonError;
 use deno_dispatch_json::JsonOp;
+use futures::future::poll_fn;
 use futures::future::FutureExt;
 use futures::future::TryFutureExt;
-use futures::task::AtomicWaker;
-use futures::task::Context;
 use futures::task::Poll;
-use futures::task::SpawnExt;
 use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::future::Future;
-use std::pin::Pin;
-use std::sync::atomic::AtomicU32;
-use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::sync::RwLock;
-use tokio::sync::Mutex;
+use std::sync::Mutex;
+use std::time::Duration;
 
-lazy_static! {
-    static ref NEXT_ISOLATE_ID: AtomicU32 = AtomicU32::new(1);
-    static ref ISOLATE_MAP: RwLock<HashMap<u32, Arc<Mutex<Box<EsIsolate>>>>> =
-        RwLock::new(HashMap::new());
+/// Guest standard library, see bootstrap.js.
+const BOOTSTRAP: &str = include_str!("bootstrap.js");
+
+#[derive(Clone)]
+struct IsolateResource {
+    pub worker: Worker,
+    pub ops: Arc<OpTable>,
+    pub completions: Arc<Completions>,
+    pub output: Arc<Output>,
+    pub termination: Arc<Termination>,
+    pub stats: Arc<Stats>,
+    pub timeout_ms: Option<u64>,
+    pub loaded_modules: Arc<LoadedModules>,
+    // Module resources to close along with the isolate.
+    pub modules: Arc<Mutex<Vec<ResourceId>>>,
+}
+
+impl Resource for IsolateResource {
+    const KIND: &'static str = "isolate";
+}
+
+/// A module evaluated by isolateExecuteModule.
+#[derive(Clone)]
+struct GuestModule {
+    pub isolate_rid: ResourceId,
+    pub id: ModuleId,
+    /// As resolved by the loader, not as the host passed it.
+    pub specifier: String,
+}
+
+impl Resource for GuestModule {
+    const KIND: &'static str = "module";
+}
+
+impl IsolateResource {
+    /// Arm a watchdog for one call, `timeout_ms` overrides the isolate's
+    /// default.
+    fn watchdog(&self, timeout_ms: Option<u64>) -> Option<ArmedWatchdog<'_>> {
+        timeout_ms
+            .or(self.timeout_ms)
+            .map(|ms| self.worker.watchdog().arm_scoped(Duration::from_millis(ms)))
+    }
 }
 
 #[derive(Deserialize)]
@@ -33,6 +98,11 @@ struct NewIsolateOptions {
     pub will_snapshot: bool,
     pub snapshot_rid: Option<u32>,
     pub loader_rid: u32,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub output: OutputMode,
+    #[serde(default)]
+    pub bootstrap: bool,
 }
 
 pub fn op_new_isolate(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
@@ -40,14 +110,12 @@ pub fn op_new_isolate(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<Json
 
     let rid = match args.snapshot_rid {
         Some(rid) => {
-            let startup_data = crate::snapshots::snapshot_as_startup_data(rid);
-            let isolate_rid =
-                op_new_isolate_inner(args.loader_rid, startup_data, args.will_snapshot);
+            let (startup_data, snapshot) = crate::snapshots::snapshot_as_startup_data(rid)?;
+            let isolate_rid = op_new_isolate_inner(args, startup_data, Some(snapshot))?;
             isolate_rid
         }
         None => {
-            let isolate_rid =
-                op_new_isolate_inner(args.loader_rid, StartupData::None, args.will_snapshot);
+            let isolate_rid = op_new_isolate_inner(args, StartupData::None, None)?;
             isolate_rid
         }
     };
@@ -56,42 +124,54 @@ pub fn op_new_isolate(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<Json
 }
 
 fn op_new_isolate_inner(
-    loader_rid: u32,
-    startup_data: StartupData,
-    will_snapshot: bool,
-) -> ResourceId {
-    let isolate_rid = NEXT_ISOLATE_ID.fetch_add(1, Ordering::SeqCst);
-    let loader = get_loader(loader_rid);
-    let isolate = EsIsolate::new(loader, startup_data, will_snapshot);
-    let mut lock = ISOLATE_MAP.write().unwrap();
-    lock.insert(isolate_rid, Arc::new(Mutex::new(isolate)));
-    isolate_rid
+    args: NewIsolateOptions,
+    startup_data: StartupData<'static>,
+    snapshot: Option<Arc<Buf>>,
+) -> Result<ResourceId, ErrBox> {
+    let loaded_modules = Arc::new(LoadedModules::default());
+    let loader = get_loader(args.loader_rid, loaded_modules.clone())?;
+    let completions = Arc::new(Completions::default());
+    let completions_ = completions.clone();
+    let input_completions = completions.clone();
+    let output = Arc::new(Output::new(args.output));
+    let output_ = output.clone();
+    let timeout_ms = args.timeout_ms;
+    let stats = Arc::new(Stats::default());
+    let (worker, op_registry) = Worker::spawn(stats.clone(), snapshot, move || {
+        let mut isolate = EsIsolate::new(loader, startup_data, args.will_snapshot);
+        isolate.register_op(COMPLETION_OP_NAME, move |control, zero_copy| {
+            completions_.record(control, zero_copy)
+        });
+        isolate.register_op(INPUT_OP_NAME, move |control, _zero_copy| {
+            input_completions.take_input(control)
+        });
+        isolate.register_op(OUTPUT_OP_NAME, move |control, zero_copy| {
+            output_.record(control, zero_copy)
+        });
+        isolate.execute("<output>", &output_shim())?;
+        if args.bootstrap {
+            isolate.execute("<bootstrap>", BOOTSTRAP)?;
+        }
+        let op_registry = isolate.op_registry.clone();
+        Ok((isolate, op_registry))
+    })?;
+    add_resource(IsolateResource {
+        termination: worker.termination().clone(),
+        worker,
+        ops: Arc::new(OpTable::new(op_registry, stats.clone())),
+        completions,
+        output,
+        stats,
+        timeout_ms,
+        loaded_modules,
+        modules: Arc::new(Mutex::new(Vec::new())),
+    })
 }
 
 #[derive(Deserialize)]
 struct IsolateIsCompleteOptions {
     pub rid: u32,
-}
-
-struct IsolateWorker {
-    pub isolate: Arc<Mutex<Box<EsIsolate>>>,
-}
-
-impl Future for IsolateWorker {
-    type Output = Result<(), ErrBox>;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let inner = self.get_mut();
-        let waker = AtomicWaker::new();
-        waker.register(cx.waker());
-        match inner.isolate.try_lock() {
-            Ok(mut isolate) => isolate.poll_unpin(cx),
-            Err(_) => {
-                waker.wake();
-                Poll::Pending
-            }
-        }
-    }
+    pub timeout_ms: Option<u64>,
 }
 
 pub fn op_isolate_is_complete(
@@ -100,11 +180,19 @@ pub fn op_isolate_is_complete(
 ) -> Result<JsonOp, ErrBox> {
     let args: IsolateIsCompleteOptions = serde_json::from_value(args)?;
 
-    let lock = ISOLATE_MAP.read().unwrap();
-    let isolate = lock.get(&args.rid).unwrap().clone();
-    drop(lock);
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    let termination = resource.termination;
+    let timeout = args
+        .timeout_ms
+        .or(resource.timeout_ms)
+        .map(Duration::from_millis);
 
-    let fut = IsolateWorker { isolate }.map_ok(|_| json!({}));
+    let fut = resource
+        .worker
+        .await_complete(timeout)
+        .map_err(move |err| termination.map_error(err))
+        .map_ok(|_| json!({}));
 
     Ok(JsonOp::Async(fut.boxed()))
 }
@@ -123,46 +211,157 @@ pub fn op_isolate_register_op(
 ) -> Result<JsonOp, ErrBox> {
     let args: IsolateRegisterOpOptions = serde_json::from_value(args)?;
 
-    let lock = ISOLATE_MAP.read().unwrap();
-    let isolate = lock.get(&args.rid).unwrap();
-    let dispatcher = get_dispatcher(args.dispatcher_rid);
-    let isolate_lock = isolate.try_lock().unwrap();
-    let op_id = isolate_lock.register_op(&args.name, move |data, zero_copy| {
-        dispatcher.dispatch(data, zero_copy)
-    });
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    let dispatcher = get_dispatcher(args.dispatcher_rid)?;
+    // The op registry is shared with the isolate's thread, so this doesn't
+    // have to wait for the isolate.
+    let op_id = resource
+        .ops
+        .register(&args.name, args.dispatcher_rid, dispatcher)?;
     Ok(JsonOp::Sync(json!({ "opId": op_id })))
 }
 
+#[derive(Deserialize)]
+struct IsolateUnregisterOpOptions {
+    pub rid: u32,
+    pub name: String,
+}
+
+pub fn op_isolate_unregister_op(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: IsolateUnregisterOpOptions = serde_json::from_value(args)?;
+
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.ops.unregister(&args.name)?;
+    Ok(JsonOp::Sync(json!({})))
+}
+
+pub fn op_isolate_replace_op(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: IsolateRegisterOpOptions = serde_json::from_value(args)?;
+
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    let dispatcher = get_dispatcher(args.dispatcher_rid)?;
+    resource
+        .ops
+        .replace(&args.name, args.dispatcher_rid, dispatcher)?;
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IsolateAttachChannelOptions {
+    pub rid: u32,
+    pub channel_rid: u32,
+    pub name: String,
+}
+
+/// Register an endpoint of a channel as op `name` and define
+/// `Deno.channels[name]` for it. Detach it again with isolateUnregisterOp.
+pub fn op_isolate_attach_channel(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: IsolateAttachChannelOptions = serde_json::from_value(args)?;
+
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    let channel = get_resource::<Arc<Channel>>(args.channel_rid)?;
+    let endpoint: Box<dyn Dispatcher> = Box::new(ChannelEndpoint::new(channel));
+    resource
+        .ops
+        .register(&args.name, args.channel_rid, Arc::new(endpoint))?;
+
+    let worker = resource.worker.clone();
+    let fut = worker
+        .call(move |isolate| -> Result<Value, ErrBox> {
+            let termination = &resource.termination;
+            termination.check()?;
+            let source = wrap_channel(&args.name);
+            resource
+                .stats
+                .time(|| isolate.execute("<isolateAttachChannel>", &source))
+                .map_err(|err| termination.map_error(guest_error(err)))?;
+            Ok(json!({}))
+        })
+        .map(|result| result.and_then(|result| result));
+
+    Ok(JsonOp::Async(fut.boxed()))
+}
+
+#[derive(Deserialize)]
+struct IsolateListOpsOptions {
+    pub rid: u32,
+}
+
+pub fn op_isolate_list_ops(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateListOpsOptions = serde_json::from_value(args)?;
+
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    Ok(JsonOp::Sync(json!({ "ops": resource.ops.list() })))
+}
+
 #[derive(Deserialize)]
 struct IsolateExecuteOptions {
     pub rid: u32,
     pub filename: String,
     pub source: String,
+    #[serde(default)]
+    pub return_value: bool,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub fail_if_busy: bool,
 }
 
 pub fn op_isolate_execute(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
     let args: IsolateExecuteOptions = serde_json::from_value(args)?;
 
-    let lock = ISOLATE_MAP.read().unwrap();
-    let isolate = lock.get(&args.rid).unwrap().clone();
-    drop(lock);
-
-    let fut = async move {
-        let mut isolate_lock = isolate.try_lock().unwrap();
-        isolate_lock.execute(&args.filename, &args.source)
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    if args.fail_if_busy && resource.worker.is_busy() {
+        return Err(busy_error());
     }
-    .map_ok(|_| json!({}))
-    .boxed();
-    let pool = futures::executor::ThreadPool::new().unwrap();
-    let fut_handle = pool.spawn_with_handle(fut).unwrap();
 
-    Ok(JsonOp::Async(fut_handle.boxed()))
+    let worker = resource.worker.clone();
+    let fut = worker
+        .call(move |isolate| -> Result<Value, ErrBox> {
+            let termination = &resource.termination;
+            // The isolate may have been terminated while this call was queued.
+            termination.check()?;
+            let watchdog = resource.watchdog(args.timeout_ms);
+            let result = resource.stats.time(|| {
+                if args.return_value {
+                    let token = resource.completions.begin(None);
+                    let source = wrap_source(token, &args.source, &args.filename);
+                    isolate.execute(&args.filename, &source)
+                } else {
+                    isolate.execute(&args.filename, &args.source)
+                }
+            });
+            drop(watchdog);
+            result.map_err(|err| termination.map_error(guest_error(err)))?;
+            if args.return_value {
+                Ok(json!({ "completion": resource.completions.take()? }))
+            } else {
+                Ok(json!({}))
+            }
+        })
+        .map(|result| result.and_then(|result| result));
+
+    Ok(JsonOp::Async(fut.boxed()))
 }
 
 #[derive(Deserialize)]
 struct IsolateExecuteModuleOptions {
     pub rid: u32,
     pub module_specifier: String,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub fail_if_busy: bool,
 }
 
 pub fn op_isolate_execute_module(
@@ -171,41 +370,397 @@ pub fn op_isolate_execute_module(
 ) -> Result<JsonOp, ErrBox> {
     let args: IsolateExecuteModuleOptions = serde_json::from_value(args)?;
 
-    let lock = ISOLATE_MAP.read().unwrap();
-    let isolate = lock.get(&args.rid).unwrap().clone();
-    drop(lock);
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    if args.fail_if_busy && resource.worker.is_busy() {
+        return Err(busy_error());
+    }
+
+    let worker = resource.worker.clone();
+    let modules = resource.modules.clone();
+    let isolate_rid = args.rid;
+    let fut = worker
+        .call(move |isolate| -> Result<(ModuleId, String), ErrBox> {
+            let termination = &resource.termination;
+            termination.check()?;
+            // Loading waits on the host's loader, nothing else runs on the
+            // isolate meanwhile.
+            let id = park_on(
+                isolate
+                    .load_module(&args.module_specifier, None)
+                    .boxed_local(),
+            )
+            .map_err(|err| termination.map_error(guest_error(err)))?;
+            let specifier = resource
+                .loaded_modules
+                .take_main()
+                .map(|specifier| specifier.to_string())
+                .unwrap_or(args.module_specifier);
+            let watchdog = resource.watchdog(args.timeout_ms);
+            let result = resource.stats.time(|| isolate.mod_evaluate(id));
+            drop(watchdog);
+            result.map_err(|err| termination.map_error(guest_error(err)))?;
+            resource.loaded_modules.mark_evaluated(specifier.clone());
+            Ok((id, specifier))
+        })
+        .map(move |result| -> Result<Value, ErrBox> {
+            let (id, specifier) = result??;
+            let rid = add_resource(GuestModule {
+                isolate_rid,
+                id,
+                specifier: specifier.clone(),
+            })?;
+            modules.lock().unwrap().push(rid);
+            Ok(json!({ "rid": rid, "specifier": specifier }))
+        });
+
+    Ok(JsonOp::Async(fut.boxed()))
+}
+
+#[derive(Deserialize)]
+struct IsolateCallOptions {
+    pub rid: u32,
+    pub name: String,
+    pub module_rid: Option<u32>,
+    #[serde(default)]
+    pub args: Vec<Value>,
+    #[serde(default)]
+    pub bytes_args: Vec<BytesArg>,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub fail_if_busy: bool,
+}
+
+fn unsettled_error() -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "Unsettled",
+        "guest promise never settled".to_string(),
+    ))
+}
+
+/// Drive the event loop until the guest reports a value through the
+/// completion op, for calls that return a promise.
+fn await_completion(
+    isolate: &mut Box<EsIsolate>,
+    resource: &IsolateResource,
+) -> Result<(), ErrBox> {
+    let completions = &resource.completions;
+    park_on(poll_fn(|cx| {
+        if completions.is_recorded() {
+            return Poll::Ready(Ok(()));
+        }
+        if let Err(err) = resource.termination.check() {
+            return Poll::Ready(Err(err));
+        }
+        match resource.stats.time(|| isolate.poll_unpin(cx)) {
+            Poll::Ready(Ok(())) if !completions.is_recorded() => {
+                Poll::Ready(Err(unsettled_error()))
+            }
+            Poll::Ready(result) => Poll::Ready(result),
+            Poll::Pending if completions.is_recorded() => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }))
+}
+
+/// Run a guest shim that reports a value through the completion op, and
+/// take that value once it is reported.
+fn report_completion(
+    isolate: &mut Box<EsIsolate>,
+    resource: &IsolateResource,
+    filename: &str,
+    source: &str,
+    timeout_ms: Option<u64>,
+) -> Result<CompletionValue, ErrBox> {
+    let watchdog = resource.watchdog(timeout_ms);
+    let result = resource
+        .stats
+        .time(|| isolate.execute(filename, source))
+        .and_then(|_| await_completion(isolate, resource));
+    drop(watchdog);
+    result.map_err(|err| resource.termination.map_error(guest_error(err)))?;
+    resource.completions.take()
+}
+
+pub fn op_isolate_call(args: Value, zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateCallOptions = serde_json::from_value(args)?;
+
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    if args.fail_if_busy && resource.worker.is_busy() {
+        return Err(busy_error());
+    }
+    // Only modules isolateExecuteModule evaluated in this isolate can be
+    // called into, a specifier could name a module that was never evaluated.
+    let module_specifier = match args.module_rid {
+        Some(module_rid) => {
+            let module = get_resource::<GuestModule>(module_rid)?;
+            if module.isolate_rid != args.rid {
+                return Err(ErrBox::from(JsonError::new(
+                    "InvalidInput",
+                    format!("module {} belongs to another isolate", module_rid),
+                )));
+            }
+            Some(module.specifier)
+        }
+        None => None,
+    };
+    let input = zero_copy.map(|buf| buf.to_vec());
+
+    let worker = resource.worker.clone();
+    let fut = worker
+        .call(move |isolate| -> Result<Value, ErrBox> {
+            let termination = &resource.termination;
+            termination.check()?;
+            let token = resource.completions.begin(input);
+            let source = wrap_call(
+                token,
+                &args.name,
+                module_specifier.as_ref().map(String::as_str),
+                &args.args,
+                &args.bytes_args,
+            );
+            let completion = report_completion(
+                isolate,
+                &resource,
+                CALL_REFERRER,
+                &source,
+                args.timeout_ms,
+            )?;
+            Ok(json!({ "completion": completion }))
+        })
+        .map(|result| result.and_then(|result| result));
+
+    Ok(JsonOp::Async(fut.boxed()))
+}
+
+#[derive(Deserialize)]
+struct IsolateSetGlobalOptions {
+    pub rid: u32,
+    pub name: String,
+    #[serde(default)]
+    pub value: Value,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub freeze: bool,
+}
+
+pub fn op_isolate_set_global(args: Value, zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateSetGlobalOptions = serde_json::from_value(args)?;
 
-    let fut = async move {
-        let mut i = isolate.try_lock().unwrap();
-        let id = i.load_module(&args.module_specifier, None).await?;
-        let result = i.mod_evaluate(id);
-        result
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    // V8 can't freeze typed arrays that have elements.
+    if args.freeze && zero_copy.is_some() {
+        return Err(ErrBox::from(JsonError::new(
+            "InvalidInput",
+            "freeze only applies to JSON values, not bytes".to_string(),
+        )));
     }
-    .map_ok(|_| json!({}))
-    .boxed();
-    let pool = futures::executor::ThreadPool::new().unwrap();
-    let fut_handle = pool.spawn_with_handle(fut).unwrap();
+    let input = zero_copy.map(|buf| buf.to_vec());
+
+    let worker = resource.worker.clone();
+    let fut = worker
+        .call(move |isolate| -> Result<Value, ErrBox> {
+            let termination = &resource.termination;
+            termination.check()?;
+            let is_bytes = input.is_some();
+            let token = resource.completions.begin(input);
+            let source = wrap_set_global(
+                token,
+                &args.name,
+                &args.value,
+                is_bytes,
+                args.read_only,
+                args.freeze,
+            );
+            let result = resource
+                .stats
+                .time(|| isolate.execute("<isolateSetGlobal>", &source));
+            resource.completions.end();
+            result.map_err(|err| termination.map_error(guest_error(err)))?;
+            Ok(json!({}))
+        })
+        .map(|result| result.and_then(|result| result));
 
-    Ok(JsonOp::Async(fut_handle.boxed()))
+    Ok(JsonOp::Async(fut.boxed()))
 }
 
 #[derive(Deserialize)]
 struct IsolateSnapshotOptions {
     pub rid: u32,
+    #[serde(default)]
+    pub fail_if_busy: bool,
 }
 
 pub fn op_isolate_snapshot(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
     let args: IsolateSnapshotOptions = serde_json::from_value(args)?;
 
-    let lock = ISOLATE_MAP.read().unwrap();
-    let isolate = lock.get(&args.rid).unwrap().clone();
-    drop(lock);
+    let resource = get_resource::<IsolateResource>(args.rid)?;
+    resource.termination.check()?;
+    if args.fail_if_busy && resource.worker.is_busy() {
+        return Err(busy_error());
+    }
+
+    // Async so the host keeps running while calls queued before the
+    // snapshot finish, they may be waiting on the host's own ops.
+    let fut = resource
+        .worker
+        .call(|isolate| -> Result<Buf, ErrBox> {
+            let snapshot = isolate.snapshot()?;
+            Ok((**snapshot).into())
+        })
+        .map(|result| -> Result<Value, ErrBox> {
+            let snapshot_buf = result??;
+            Ok(json!(ResourceIdResponse {
+                rid: crate::snapshots::new_snapshot(snapshot_buf)?,
+            }))
+        });
+
+    Ok(JsonOp::Async(fut.boxed()))
+}
+
+#[derive(Deserialize)]
+struct IsolateCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_isolate_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateCloseOptions = serde_json::from_value(args)?;
+
+    // Calls already queued on the isolate still run, its thread drops the V8
+    // isolate once they finish.
+    let resource = take_resource::<IsolateResource>(args.rid)?;
+    resource.output.close();
+    for module_rid in resource.modules.lock().unwrap().drain(..) {
+        let _ = take_resource::<GuestModule>(module_rid);
+    }
+
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+struct IsolateOutputOptions {
+    pub rid: u32,
+}
+
+pub fn op_isolate_read_output(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: IsolateOutputOptions = serde_json::from_value(args)?;
+
+    let output = get_resource::<IsolateResource>(args.rid)?.output;
+    Ok(JsonOp::Sync(json!({ "chunks": output.take() })))
+}
+
+pub fn op_isolate_await_output(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: IsolateOutputOptions = serde_json::from_value(args)?;
+
+    let output = get_resource::<IsolateResource>(args.rid)?.output;
+    let op = OutputWorker { output };
+
+    Ok(JsonOp::Async(op.boxed()))
+}
+
+#[derive(Deserialize)]
+struct IsolateReadBytesOptions {
+    pub rid: u32,
+    pub id: u32,
+}
+
+pub fn op_isolate_read_bytes(args: Value, zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateReadBytesOptions = serde_json::from_value(args)?;
+    let mut out = zero_copy.ok_or_else(|| {
+        ErrBox::from(JsonError::new(
+            "InvalidInput",
+            "isolateReadBytes needs a buffer".to_string(),
+        ))
+    })?;
+
+    let completions = get_resource::<IsolateResource>(args.rid)?.completions;
+    completions.read_bytes(args.id, &mut out[..])?;
+
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+struct IsolateTerminateOptions {
+    pub rid: u32,
+}
+
+pub fn op_isolate_terminate(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateTerminateOptions = serde_json::from_value(args)?;
+
+    let termination = get_resource::<IsolateResource>(args.rid)?.termination;
+    termination.terminate();
 
-    let mut i = isolate.try_lock().unwrap();
-    let snapshot = i.snapshot()?;
-    let snapshot_buf: Buf = (**snapshot).into();
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+struct IsolateStatsOptions {
+    pub rid: u32,
+}
+
+pub fn op_isolate_stats(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: IsolateStatsOptions = serde_json::from_value(args)?;
+
+    let stats = get_resource::<IsolateResource>(args.rid)?.stats;
+
+    Ok(JsonOp::Sync(json!(stats.snapshot())))
+}
+
+#[derive(Deserialize)]
+struct ModuleNamespaceOptions {
+    pub rid: u32,
+    pub timeout_ms: Option<u64>,
+}
+
+pub fn op_module_namespace(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: ModuleNamespaceOptions = serde_json::from_value(args)?;
+
+    let module = get_resource::<GuestModule>(args.rid)?;
+    let resource = get_resource::<IsolateResource>(module.isolate_rid)?;
+    resource.termination.check()?;
+
+    let worker = resource.worker.clone();
+    let fut = worker
+        .call(move |isolate| -> Result<Value, ErrBox> {
+            resource.termination.check()?;
+            let token = resource.completions.begin(None);
+            let source = wrap_namespace(token, &module.specifier);
+            let completion = report_completion(
+                isolate,
+                &resource,
+                NAMESPACE_REFERRER,
+                &source,
+                args.timeout_ms,
+            )?;
+            Ok(json!({ "id": module.id, "exports": completion }))
+        })
+        .map(|result| result.and_then(|result| result));
+
+    Ok(JsonOp::Async(fut.boxed()))
+}
+
+#[derive(Deserialize)]
+struct ModuleCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_module_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: ModuleCloseOptions = serde_json::from_value(args)?;
+
+    let module = take_resource::<GuestModule>(args.rid)?;
+    if let Ok(resource) = get_resource::<IsolateResource>(module.isolate_rid) {
+        let mut modules = resource.modules.lock().unwrap();
+        modules.retain(|rid| *rid != args.rid);
+    }
 
-    Ok(JsonOp::Sync(json!(ResourceIdResponse {
-        rid: crate::snapshots::new_snapshot(snapshot_buf.into()),
-    })))
+    Ok(JsonOp::Sync(json!({})))
 }
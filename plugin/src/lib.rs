@@ -4,15 +4,26 @@ use deno_dispatch_json::json_op;
 #[macro_use]
 extern crate lazy_static;
 
+mod channel;
+mod completion;
 mod dispatch;
+mod errors;
 mod isolate;
 mod modules;
 mod msg;
+mod op_table;
+mod output;
+mod resources;
 mod snapshots;
+mod stats;
+mod termination;
+mod util;
+mod worker;
 
 pub use dispatch::Dispatcher;
 pub use dispatch::GetDispatcherAccessor;
 pub use dispatch::InsertDispatcherAccessor;
+pub use dispatch::TryGetDispatcherAccessor;
 
 pub fn init(cx: &mut dyn PluginInitContext) {
     // Dispatch ops
@@ -20,6 +31,10 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "getDispatcherAccessorPtrs",
         json_op(Box::new(dispatch::op_get_dispatcher_accessor_ptrs)),
     );
+    cx.register_op(
+        "dispatcherClose",
+        json_op(Box::new(dispatch::op_dispatcher_close)),
+    );
     cx.register_op(
         "newStdDispatcher",
         json_op(Box::new(dispatch::op_new_std_dispatcher)),
@@ -28,10 +43,22 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "stdDispatcherWaitForDispatch",
         json_op(Box::new(dispatch::op_std_dispatcher_wait_for_dispatch)),
     );
+    cx.register_op(
+        "stdDispatcherReadRequest",
+        json_op(Box::new(dispatch::op_std_dispatcher_read_request)),
+    );
     cx.register_op(
         "stdDispatcherRespond",
         json_op(Box::new(dispatch::op_std_dispatcher_respond)),
     );
+    cx.register_op(
+        "stdDispatcherRespondAsync",
+        json_op(Box::new(dispatch::op_std_dispatcher_respond_async)),
+    );
+    cx.register_op(
+        "stdDispatcherClose",
+        json_op(Box::new(dispatch::op_std_dispatcher_close)),
+    );
 
     // Isolate ops
     cx.register_op("newIsolate", json_op(Box::new(isolate::op_new_isolate)));
@@ -43,20 +70,68 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "isolateRegisterOp",
         json_op(Box::new(isolate::op_isolate_register_op)),
     );
+    cx.register_op(
+        "isolateUnregisterOp",
+        json_op(Box::new(isolate::op_isolate_unregister_op)),
+    );
+    cx.register_op(
+        "isolateReplaceOp",
+        json_op(Box::new(isolate::op_isolate_replace_op)),
+    );
+    cx.register_op(
+        "isolateAttachChannel",
+        json_op(Box::new(isolate::op_isolate_attach_channel)),
+    );
+    cx.register_op(
+        "isolateListOps",
+        json_op(Box::new(isolate::op_isolate_list_ops)),
+    );
     cx.register_op(
         "isolateExecute",
         json_op(Box::new(isolate::op_isolate_execute)),
     );
+    cx.register_op(
+        "isolateReadBytes",
+        json_op(Box::new(isolate::op_isolate_read_bytes)),
+    );
+    cx.register_op(
+        "isolateReadOutput",
+        json_op(Box::new(isolate::op_isolate_read_output)),
+    );
+    cx.register_op(
+        "isolateAwaitOutput",
+        json_op(Box::new(isolate::op_isolate_await_output)),
+    );
     cx.register_op(
         "isolateExecuteModule",
         json_op(Box::new(isolate::op_isolate_execute_module)),
     );
+    cx.register_op("isolateCall", json_op(Box::new(isolate::op_isolate_call)));
+    cx.register_op(
+        "isolateSetGlobal",
+        json_op(Box::new(isolate::op_isolate_set_global)),
+    );
     cx.register_op(
         "isolateSnapshot",
         json_op(Box::new(isolate::op_isolate_snapshot)),
     );
+    cx.register_op(
+        "isolateStats",
+        json_op(Box::new(isolate::op_isolate_stats)),
+    );
+    cx.register_op(
+        "isolateTerminate",
+        json_op(Box::new(isolate::op_isolate_terminate)),
+    );
+    cx.register_op("isolateClose", json_op(Box::new(isolate::op_isolate_close)));
 
     // Module ops
+    cx.register_op(
+        "moduleNamespace",
+        json_op(Box::new(isolate::op_module_namespace)),
+    );
+    cx.register_op("moduleClose", json_op(Box::new(isolate::op_module_close)));
+    cx.register_op("loaderClose", json_op(Box::new(modules::op_loader_close)));
     cx.register_op(
         "newStdLoader",
         json_op(Box::new(modules::op_new_std_loader)),
@@ -69,6 +144,10 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "stdLoaderRespondResolve",
         json_op(Box::new(modules::op_std_loader_respond_resolve)),
     );
+    cx.register_op(
+        "stdLoaderRejectResolve",
+        json_op(Box::new(modules::op_std_loader_reject_resolve)),
+    );
     cx.register_op(
         "stdLoaderAwaitLoad",
         json_op(Box::new(modules::op_std_loader_await_load)),
@@ -77,6 +156,17 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "stdLoaderRespondLoad",
         json_op(Box::new(modules::op_std_loader_respond_load)),
     );
+    cx.register_op(
+        "stdLoaderRejectLoad",
+        json_op(Box::new(modules::op_std_loader_reject_load)),
+    );
+    cx.register_op(
+        "stdLoaderClose",
+        json_op(Box::new(modules::op_std_loader_close)),
+    );
+
+    // Resource ops
+    cx.register_op("resources", json_op(Box::new(resources::op_resources)));
 
     // Snapshot ops
     cx.register_op("newSnapshot", json_op(Box::new(snapshots::op_new_snapshot)));
@@ -84,6 +174,17 @@ pub fn init(cx: &mut dyn PluginInitContext) {
         "snapshotRead",
         json_op(Box::new(snapshots::op_snapshot_read)),
     );
+    cx.register_op(
+        "snapshotClose",
+        json_op(Box::new(snapshots::op_snapshot_close)),
+    );
+
+    // Channel ops
+    cx.register_op("newChannel", json_op(Box::new(channel::op_new_channel)));
+    cx.register_op(
+        "channelClose",
+        json_op(Box::new(channel::op_channel_close)),
+    );
 }
 
 init_fn!(init);
@@ -1,5 +1,12 @@
 use crate::msg::ResourceId;
+use crate::resources::add_resource;
+use crate::resources::bad_resource;
+use crate::resources::get_resource;
+use crate::resources::take_resource;
+use crate::resources::Resource;
+use crate::util::park_on;
 use deno_core::*;
+use deno_dispatch_json::JsonError;
 use deno_dispatch_json::JsonOp;
 use futures::channel::oneshot;
 use futures::future::FutureExt;
@@ -9,9 +16,11 @@ use serde::Serialize;
 use serde_json::json;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -20,15 +29,59 @@ use std::sync::RwLock;
 use std::task::Context;
 use std::task::Poll;
 
-lazy_static! {
-    static ref NEXT_LOADER_ID: AtomicU32 = AtomicU32::new(1);
-    static ref LOADER_MAP: RwLock<HashMap<u32, Arc<Box<dyn Loader>>>> = RwLock::new(HashMap::new());
-    static ref NEXT_STD_LOADER_ID: AtomicU32 = AtomicU32::new(1);
-    static ref STD_LOADER_MAP: RwLock<HashMap<u32, Arc<StdLoader>>> = RwLock::new(HashMap::new());
+impl Resource for Arc<Box<dyn Loader>> {
+    const KIND: &'static str = "loader";
+}
+
+/// Referrers of the dynamic imports the plugin's own shims use to look up
+/// modules it evaluated, see call.js and namespace.js.
+pub const CALL_REFERRER: &str = "<isolateCall>";
+pub const NAMESPACE_REFERRER: &str = "<moduleNamespace>";
+
+/// What one isolate's loader resolved for the plugin's own loads.
+#[derive(Default)]
+pub struct LoadedModules {
+    main: Mutex<Option<ModuleSpecifier>>,
+    evaluated: Mutex<HashSet<String>>,
+    internal_loads: Mutex<HashSet<String>>,
+}
+
+impl LoadedModules {
+    /// Resolved specifier of the last module loaded with load_module, which
+    /// deno_core resolves as the main module.
+    pub fn take_main(&self) -> Option<ModuleSpecifier> {
+        self.main.lock().unwrap().take()
+    }
+
+    /// Record a module evaluated by isolateExecuteModule, by its resolved
+    /// specifier.
+    pub fn mark_evaluated(&self, specifier: String) {
+        self.evaluated.lock().unwrap().insert(specifier);
+    }
+
+    /// Whether this is a shim looking up a module the plugin evaluated.
+    fn is_internal(&self, specifier: &str, referrer: &str, is_dyn_import: bool) -> bool {
+        is_dyn_import
+            && (referrer == CALL_REFERRER || referrer == NAMESPACE_REFERRER)
+            && self.evaluated.lock().unwrap().contains(specifier)
+    }
+
+    fn add_internal_load(&self, specifier: &str) {
+        self.internal_loads
+            .lock()
+            .unwrap()
+            .insert(specifier.to_string());
+    }
+
+    /// Whether `specifier` was resolved as an internal lookup, once.
+    fn take_internal_load(&self, specifier: &str) -> bool {
+        self.internal_loads.lock().unwrap().remove(specifier)
+    }
 }
 
 struct LoaderWrapper {
     pub inner: Arc<Box<dyn Loader>>,
+    pub loaded: Arc<LoadedModules>,
 }
 
 impl Loader for LoaderWrapper {
@@ -39,9 +92,20 @@ impl Loader for LoaderWrapper {
         is_main: bool,
         is_dyn_import: bool,
     ) -> Result<ModuleSpecifier, ErrBox> {
-        self.inner
+        // The plugin's own lookups stay out of the host's loader and its
+        // dynamic import gate, the module is already evaluated.
+        if self.loaded.is_internal(specifier, referrer, is_dyn_import) {
+            self.loaded.add_internal_load(specifier);
+            return Ok(ModuleSpecifier::resolve_url(specifier)?);
+        }
+        let resolved = self
+            .inner
             .as_ref()
-            .resolve(specifier, referrer, is_main, is_dyn_import)
+            .resolve(specifier, referrer, is_main, is_dyn_import)?;
+        if is_main {
+            *self.loaded.main.lock().unwrap() = Some(resolved.clone());
+        }
+        Ok(resolved)
     }
 
     fn load(
@@ -49,23 +113,42 @@ impl Loader for LoaderWrapper {
         module_specifier: &ModuleSpecifier,
         maybe_referrer: Option<ModuleSpecifier>,
     ) -> Pin<Box<SourceCodeInfoFuture>> {
+        let specifier = module_specifier.to_string();
+        if self.loaded.take_internal_load(&specifier) {
+            // deno_core ignores the code of modules it already registered.
+            return Box::pin(futures::future::ready(Ok(SourceCodeInfo {
+                module_url_specified: specifier.clone(),
+                module_url_found: specifier,
+                code: String::new(),
+            })));
+        }
         self.inner.as_ref().load(module_specifier, maybe_referrer)
     }
 }
 
-pub fn insert_loader(loader: Arc<Box<dyn Loader>>) -> ResourceId {
-    let rid = NEXT_LOADER_ID.fetch_add(1, Ordering::SeqCst);
-    let mut lock = LOADER_MAP.write().unwrap();
-    lock.insert(rid, loader);
-    rid
+pub fn insert_loader(loader: Arc<Box<dyn Loader>>) -> Result<ResourceId, ErrBox> {
+    add_resource(loader)
+}
+
+/// Wrap the loader `loader_rid` for one isolate, recording what it resolves
+/// into `loaded`.
+pub fn get_loader(
+    loader_rid: ResourceId,
+    loaded: Arc<LoadedModules>,
+) -> Result<Box<dyn Loader + Unpin>, ErrBox> {
+    let inner = get_resource(loader_rid)?;
+    Ok(Box::new(LoaderWrapper { inner, loaded }))
 }
 
-pub fn get_loader(loader_rid: ResourceId) -> Box<dyn Loader + Unpin> {
-    let lock = LOADER_MAP.read().unwrap();
-    let loader_ref = lock.get(&loader_rid).unwrap();
-    Box::new(LoaderWrapper {
-        inner: Arc::clone(loader_ref),
-    })
+#[derive(Deserialize)]
+struct LoaderCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_loader_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: LoaderCloseOptions = serde_json::from_value(args)?;
+    take_resource::<Arc<Box<dyn Loader>>>(args.rid)?;
+    Ok(JsonOp::Sync(json!({})))
 }
 
 type StdLoaderResolveReq = (u32, String, String, bool, bool);
@@ -76,8 +159,24 @@ type StdLoaderLoadReq = (u32, String, Option<String>);
 type StdLoaderLoadReqQueue = VecDeque<StdLoaderLoadReq>;
 type StdLoaderLoadRes = Result<SourceCodeInfo, ErrBox>;
 
+fn loader_closed_error() -> ErrBox {
+    ErrBox::from(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "StdLoader is closed",
+    ))
+}
+
+fn dyn_import_refused_error(specifier: &str) -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "DynamicImportRefused",
+        format!("dynamic import of \"{}\" is not allowed", specifier),
+    ))
+}
+
 // TODO(afinch7) maybe break this into two structs Resolver + Loader
 pub struct StdLoader {
+    pub closed: AtomicBool,
+    pub allow_dyn_import: bool,
     pub next_resolve_id: AtomicU32,
     pub resolve_res_senders: Arc<RwLock<HashMap<u32, oneshot::Sender<StdLoaderResolveRes>>>>,
     pub resolve_req_queue: Arc<Mutex<StdLoaderResolveReqQueue>>,
@@ -89,8 +188,10 @@ pub struct StdLoader {
 }
 
 impl StdLoader {
-    pub fn new() -> Self {
+    pub fn new(allow_dyn_import: bool) -> Self {
         Self {
+            closed: AtomicBool::new(false),
+            allow_dyn_import,
             next_resolve_id: AtomicU32::new(0),
             resolve_res_senders: Arc::new(RwLock::new(HashMap::new())),
             resolve_req_queue: Arc::new(Mutex::new(VecDeque::new())),
@@ -101,6 +202,24 @@ impl StdLoader {
             load_waker: AtomicWaker::new(),
         }
     }
+
+    pub fn close(&self) {
+        {
+            let mut resolve_lock = self.resolve_res_senders.write().unwrap();
+            let mut load_lock = self.load_res_senders.write().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+            for (_, sender) in resolve_lock.drain() {
+                let _ = sender.send(Err(loader_closed_error()));
+            }
+            for (_, (_, sender)) in load_lock.drain() {
+                let _ = sender.send(Err(loader_closed_error()));
+            }
+            self.resolve_req_queue.lock().unwrap().clear();
+            self.load_req_queue.lock().unwrap().clear();
+        }
+        self.resolve_waker.wake();
+        self.load_waker.wake();
+    }
 }
 
 impl Loader for StdLoader {
@@ -111,10 +230,18 @@ impl Loader for StdLoader {
         is_main: bool,
         is_dyn_import: bool,
     ) -> Result<ModuleSpecifier, ErrBox> {
+        // Refused before reaching the host. The error rejects the guest's
+        // import() promise.
+        if is_dyn_import && !self.allow_dyn_import {
+            return Err(dyn_import_refused_error(specifier));
+        }
         let cmd_id = self.next_resolve_id.fetch_add(1, Ordering::SeqCst);
-        let (res_sender, mut res_reciever) = oneshot::channel::<StdLoaderResolveRes>();
+        let (res_sender, res_reciever) = oneshot::channel::<StdLoaderResolveRes>();
         {
             let mut lock = self.resolve_res_senders.write().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(loader_closed_error());
+            }
             lock.insert(cmd_id, res_sender);
             let mut queue = self.resolve_req_queue.lock().unwrap();
             queue.push_back((
@@ -126,14 +253,10 @@ impl Loader for StdLoader {
             ));
         }
         self.resolve_waker.wake();
-        // TODO(afinch7) This is a realy ugly hack. Find a better solution here.
-        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
-        loop {
-            match res_reciever.poll_unpin(&mut cx) {
-                Poll::Ready(v) => return v.unwrap(),
-                Poll::Pending => {}
-            };
-        }
+        // Loader::resolve is synchronous, so park the isolate thread until the
+        // host answers. The host may take as long as it likes, e.g. awaiting
+        // an import map read, without costing any CPU here.
+        park_on(res_reciever).unwrap_or_else(|_| Err(loader_closed_error()))
     }
 
     fn load(
@@ -145,6 +268,9 @@ impl Loader for StdLoader {
         let (res_sender, res_reciever) = oneshot::channel::<StdLoaderLoadRes>();
         {
             let mut lock = self.load_res_senders.write().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return futures::future::err(loader_closed_error()).boxed();
+            }
             let module_url_specified = module_specifier.as_url().to_string();
             lock.insert(cmd_id, (module_url_specified.clone(), res_sender));
             let mut queue = self.load_req_queue.lock().unwrap();
@@ -155,10 +281,16 @@ impl Loader for StdLoader {
             ));
         }
         self.load_waker.wake();
-        res_reciever.map(|r| r.unwrap()).boxed()
+        res_reciever
+            .map(|r| r.unwrap_or_else(|_| Err(loader_closed_error())))
+            .boxed()
     }
 }
 
+impl Resource for Arc<StdLoader> {
+    const KIND: &'static str = "stdLoader";
+}
+
 struct StdLoaderArcWrapper {
     pub inner: Arc<StdLoader>,
 }
@@ -191,14 +323,23 @@ struct NewStdDispatcherResponse {
     pub loader_rid: u32,
 }
 
-pub fn op_new_std_loader(_args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
-    let std_rid = NEXT_STD_LOADER_ID.fetch_add(1, Ordering::SeqCst);
-    let loader = Arc::new(StdLoader::new());
-    let mut lock = STD_LOADER_MAP.write().unwrap();
-    lock.insert(std_rid, Arc::clone(&loader));
+#[derive(Deserialize)]
+struct NewStdLoaderOptions {
+    #[serde(default = "default_allow_dyn_import")]
+    pub allow_dyn_import: bool,
+}
+
+fn default_allow_dyn_import() -> bool {
+    true
+}
+
+pub fn op_new_std_loader(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: NewStdLoaderOptions = serde_json::from_value(args)?;
+    let loader = Arc::new(StdLoader::new(args.allow_dyn_import));
+    let std_rid = add_resource(Arc::clone(&loader))?;
     let rid = insert_loader(Arc::new(
         Box::new(StdLoaderArcWrapper { inner: loader }) as Box<dyn Loader>
-    ));
+    ))?;
 
     Ok(JsonOp::Sync(json!(NewStdDispatcherResponse {
         std_loader_rid: std_rid,
@@ -216,27 +357,31 @@ struct StdLoaderAwaitResolveResponse {
     pub cmd_id: u32,
     pub specifier: String,
     pub referrer: String,
-    pub is_root: bool,
+    pub is_main: bool,
+    pub is_dyn_import: bool,
 }
 
 struct ResolveWorker {
-    pub rid: u32,
+    pub loader: Arc<StdLoader>,
 }
 
 impl Future for ResolveWorker {
     type Output = Result<Value, ErrBox>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let lock = STD_LOADER_MAP.read().unwrap();
-        let loader = lock.get(&self.rid).unwrap();
+        let loader = &self.loader;
         loader.resolve_waker.register(cx.waker());
+        if loader.closed.load(Ordering::SeqCst) {
+            return Poll::Ready(Ok(json!({ "closed": true })));
+        }
         let mut queue = loader.resolve_req_queue.lock().unwrap();
         let result = match queue.pop_front() {
             Some(req) => Poll::Ready(Ok(json!(StdLoaderAwaitResolveResponse {
                 cmd_id: req.0,
                 specifier: req.1,
                 referrer: req.2,
-                is_root: req.3,
+                is_main: req.3,
+                is_dyn_import: req.4,
             }))),
             None => Poll::Pending,
         };
@@ -250,7 +395,9 @@ pub fn op_std_loader_await_resolve(
 ) -> Result<JsonOp, ErrBox> {
     let args: StdLoaderAwaitResolveOptions = serde_json::from_value(args)?;
 
-    let op = ResolveWorker { rid: args.rid };
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
+
+    let op = ResolveWorker { loader };
 
     Ok(JsonOp::Async(op.boxed()))
 }
@@ -268,19 +415,49 @@ pub fn op_std_loader_respond_resolve(
 ) -> Result<JsonOp, ErrBox> {
     let args: StdLoaderRespondResolveOptions = serde_json::from_value(args)?;
 
-    let lock = STD_LOADER_MAP.read().unwrap();
-    let loader = lock.get(&args.rid).unwrap();
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
     let mut senders_lock = loader.resolve_res_senders.write().unwrap();
-    let sender = senders_lock.remove(&args.cmd_id).unwrap();
+    let sender = senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("resolve request", args.cmd_id))?;
     let result = ModuleSpecifier::resolve_url(&args.module_specifier);
     let js_result = match &result {
         Ok(_) => Ok(JsonOp::Sync(json!({}))),
         Err(err) => Err(ErrBox::from(err.clone())),
     };
-    sender.send(result.map_err(ErrBox::from)).unwrap();
+    // The guest side may already be gone, there is nobody to report that to.
+    let _ = sender.send(result.map_err(ErrBox::from));
     js_result
 }
 
+/// A host rejection of a resolve or load request, `kind` defaults to "Error".
+#[derive(Deserialize)]
+struct StdLoaderRejectOptions {
+    pub rid: u32,
+    pub cmd_id: u32,
+    pub kind: Option<String>,
+    pub message: String,
+}
+
+fn rejection_error(kind: Option<String>, message: String) -> ErrBox {
+    ErrBox::from(JsonError::new(kind.as_deref().unwrap_or("Error"), message))
+}
+
+pub fn op_std_loader_reject_resolve(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: StdLoaderRejectOptions = serde_json::from_value(args)?;
+
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
+    let mut senders_lock = loader.resolve_res_senders.write().unwrap();
+    let sender = senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("resolve request", args.cmd_id))?;
+    let _ = sender.send(Err(rejection_error(args.kind, args.message)));
+    Ok(JsonOp::Sync(json!({})))
+}
+
 #[derive(Deserialize)]
 struct StdLoaderAwaitLoadOptions {
     pub rid: u32,
@@ -294,16 +471,18 @@ struct StdLoaderAwaitLoadResponse {
 }
 
 struct LoadWorker {
-    pub rid: u32,
+    pub loader: Arc<StdLoader>,
 }
 
 impl Future for LoadWorker {
     type Output = Result<Value, ErrBox>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        let lock = STD_LOADER_MAP.read().unwrap();
-        let loader = lock.get(&self.rid).unwrap();
+        let loader = &self.loader;
         loader.load_waker.register(cx.waker());
+        if loader.closed.load(Ordering::SeqCst) {
+            return Poll::Ready(Ok(json!({ "closed": true })));
+        }
         let mut queue = loader.load_req_queue.lock().unwrap();
         let result = match queue.pop_front() {
             Some(req) => Poll::Ready(Ok(json!(StdLoaderAwaitLoadResponse {
@@ -323,7 +502,9 @@ pub fn op_std_loader_await_load(
 ) -> Result<JsonOp, ErrBox> {
     let args: StdLoaderAwaitResolveOptions = serde_json::from_value(args)?;
 
-    let op = LoadWorker { rid: args.rid };
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
+
+    let op = LoadWorker { loader };
 
     Ok(JsonOp::Async(op.boxed()))
 }
@@ -342,16 +523,43 @@ pub fn op_std_loader_respond_load(
 ) -> Result<JsonOp, ErrBox> {
     let args: StdLoaderRespondLoadOptions = serde_json::from_value(args)?;
 
-    let lock = STD_LOADER_MAP.read().unwrap();
-    let loader = lock.get(&args.rid).unwrap();
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
+    let mut senders_lock = loader.load_res_senders.write().unwrap();
+    let (module_url_specified, sender) = senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("load request", args.cmd_id))?;
+    // The isolate may have been closed while the load was pending.
+    let _ = sender.send(Ok(SourceCodeInfo {
+        module_url_specified,
+        module_url_found: args.module_name,
+        code: args.code,
+    }));
+    Ok(JsonOp::Sync(json!({})))
+}
+
+#[derive(Deserialize)]
+struct StdLoaderCloseOptions {
+    pub rid: u32,
+}
+
+pub fn op_std_loader_close(args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let args: StdLoaderCloseOptions = serde_json::from_value(args)?;
+    let loader = take_resource::<Arc<StdLoader>>(args.rid)?;
+    loader.close();
+    Ok(JsonOp::Sync(json!({})))
+}
+
+pub fn op_std_loader_reject_load(
+    args: Value,
+    _zero_copy: Option<PinnedBuf>,
+) -> Result<JsonOp, ErrBox> {
+    let args: StdLoaderRejectOptions = serde_json::from_value(args)?;
+
+    let loader = get_resource::<Arc<StdLoader>>(args.rid)?;
     let mut senders_lock = loader.load_res_senders.write().unwrap();
-    let (module_url_specified, sender) = senders_lock.remove(&args.cmd_id).unwrap();
-    assert!(sender
-        .send(Ok(SourceCodeInfo {
-            module_url_specified,
-            module_url_found: args.module_name,
-            code: args.code,
-        }))
-        .is_ok());
+    let (_, sender) = senders_lock
+        .remove(&args.cmd_id)
+        .ok_or_else(|| bad_resource("load request", args.cmd_id))?;
+    let _ = sender.send(Err(rejection_error(args.kind, args.message)));
     Ok(JsonOp::Sync(json!({})))
 }
@@ -0,0 +1,24 @@
+// Lists the exports of the already evaluated module `specifier` for the
+// moduleNamespace op. Exports JSON can't represent are listed without a
+// value.
+((completion, specifier) => {
+  function describe(namespace, name) {
+    const entry = { name, type: "undefined" };
+    try {
+      const value = namespace[name];
+      entry.type = typeof value;
+      if (JSON.stringify(value) !== undefined) {
+        entry.value = value;
+      }
+    } catch (_) {
+      // Uninitialized bindings and values like BigInt have no JSON form.
+    }
+    return entry;
+  }
+
+  import(specifier)
+    .then(namespace =>
+      Object.keys(namespace).map(name => describe(namespace, name))
+    )
+    .then(completion.report, completion.reportError);
+})
@@ -0,0 +1,155 @@
+use crate::dispatch::Dispatcher;
+use crate::msg::ResourceId;
+use crate::stats::Stats;
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+type DispatcherSlot = Arc<RwLock<Option<(ResourceId, Arc<Box<dyn Dispatcher>>)>>>;
+
+struct OpEntry {
+    op_id: OpId,
+    slot: DispatcherSlot,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpInfo {
+    pub name: String,
+    pub op_id: OpId,
+    pub dispatcher_rid: ResourceId,
+}
+
+fn op_exists_error(name: &str) -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "AlreadyExists",
+        format!("op \"{}\" is already registered", name),
+    ))
+}
+
+fn op_not_found_error(name: &str) -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "NotFound",
+        format!("op \"{}\" is not registered", name),
+    ))
+}
+
+/// Sync response of an op without a dispatcher: a JSON error envelope like
+/// the ones deno_dispatch_json sends, with kind "NotFound". JSON ops reject
+/// with it, raw ops get a non empty buffer where a live op would have
+/// responded asynchronously or with its own bytes.
+fn revoked_response(name: &str) -> Buf {
+    let envelope = json!({
+        "err": {
+            "kind": "NotFound",
+            "message": format!("op \"{}\" is not registered", name),
+        }
+    });
+    serde_json::to_vec(&envelope).unwrap().into_boxed_slice()
+}
+
+/// The host registered ops of one isolate. deno_core can't remove an op
+/// once registered, so each op dispatches through a slot that can be
+/// emptied or refilled. An emptied op stays in `Deno.core.ops()` but
+/// answers every call synchronously with `revoked_response`.
+pub struct OpTable {
+    registry: Arc<OpRegistry>,
+    stats: Arc<Stats>,
+    ops: Mutex<BTreeMap<String, OpEntry>>,
+}
+
+impl OpTable {
+    pub fn new(registry: Arc<OpRegistry>, stats: Arc<Stats>) -> Self {
+        Self {
+            registry,
+            stats,
+            ops: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Bind `dispatcher` to `name`. A name that was unregistered before is
+    /// bound again under its old op id.
+    pub fn register(
+        &self,
+        name: &str,
+        dispatcher_rid: ResourceId,
+        dispatcher: Arc<Box<dyn Dispatcher>>,
+    ) -> Result<OpId, ErrBox> {
+        let mut ops = self.ops.lock().unwrap();
+        if let Some(entry) = ops.get(name) {
+            let mut slot = entry.slot.write().unwrap();
+            if slot.is_some() {
+                return Err(op_exists_error(name));
+            }
+            *slot = Some((dispatcher_rid, dispatcher));
+            self.stats.op_registered();
+            return Ok(entry.op_id);
+        }
+        let slot: DispatcherSlot = Arc::new(RwLock::new(Some((dispatcher_rid, dispatcher))));
+        let slot_ = slot.clone();
+        let stats = self.stats.clone();
+        let name_ = name.to_string();
+        let op_id = self.registry.register(name, move |data, zero_copy| {
+            // Clone the dispatcher out so a replace doesn't wait on a call
+            // that blocks, e.g. a sync only StdDispatcher.
+            let dispatcher = slot_.read().unwrap().as_ref().map(|(_, d)| d.clone());
+            match dispatcher {
+                Some(dispatcher) => stats.track_op(dispatcher.dispatch(data, zero_copy)),
+                None => Op::Sync(revoked_response(&name_)),
+            }
+        });
+        ops.insert(name.to_string(), OpEntry { op_id, slot });
+        self.stats.op_registered();
+        Ok(op_id)
+    }
+
+    pub fn unregister(&self, name: &str) -> Result<(), ErrBox> {
+        let ops = self.ops.lock().unwrap();
+        let entry = ops.get(name).ok_or_else(|| op_not_found_error(name))?;
+        if entry.slot.write().unwrap().take().is_none() {
+            return Err(op_not_found_error(name));
+        }
+        self.stats.op_unregistered();
+        Ok(())
+    }
+
+    pub fn replace(
+        &self,
+        name: &str,
+        dispatcher_rid: ResourceId,
+        dispatcher: Arc<Box<dyn Dispatcher>>,
+    ) -> Result<(), ErrBox> {
+        let ops = self.ops.lock().unwrap();
+        let entry = ops.get(name).ok_or_else(|| op_not_found_error(name))?;
+        let mut slot = entry.slot.write().unwrap();
+        if slot.is_none() {
+            return Err(op_not_found_error(name));
+        }
+        *slot = Some((dispatcher_rid, dispatcher));
+        Ok(())
+    }
+
+    /// Ops that currently have a dispatcher, by name.
+    pub fn list(&self) -> Vec<OpInfo> {
+        let ops = self.ops.lock().unwrap();
+        ops.iter()
+            .filter_map(|(name, entry)| {
+                entry
+                    .slot
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .map(|(dispatcher_rid, _)| OpInfo {
+                        name: name.clone(),
+                        op_id: entry.op_id,
+                        dispatcher_rid: *dispatcher_rid,
+                    })
+            })
+            .collect()
+    }
+}
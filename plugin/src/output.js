@@ -0,0 +1,38 @@
+// Sends Deno.core.print and console output through the internal output op,
+// so the plugin can capture it per isolate. Text is encoded with
+// `toUtf16`, see utf16.js. Running this again, e.g. in an isolate made from
+// a snapshot, is harmless.
+(toUtf16 => {
+  const opId = Deno.core.ops()["__output"];
+
+  Deno.core.print = (str, isErr) => {
+    const chars = toUtf16(String(str));
+    Deno.core.dispatch(opId, new Uint8Array([isErr ? 1 : 0]), chars);
+  };
+
+  function format(args) {
+    return args
+      .map(arg => {
+        if (typeof arg === "string") {
+          return arg;
+        }
+        try {
+          const json = JSON.stringify(arg);
+          return json === undefined ? String(arg) : json;
+        } catch (err) {
+          return String(arg);
+        }
+      })
+      .join(" ");
+  }
+
+  const log = (...args) => Deno.core.print(format(args) + "\n", false);
+  const error = (...args) => Deno.core.print(format(args) + "\n", true);
+  globalThis.console = {
+    log,
+    info: log,
+    debug: log,
+    warn: error,
+    error
+  };
+})
@@ -0,0 +1,153 @@
+use crate::util::utf16_units;
+use crate::util::UTF16_SHIM;
+use deno_core::*;
+use futures::future::Future;
+use futures::task::AtomicWaker;
+use futures::task::Context;
+use futures::task::Poll;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+pub const OUTPUT_OP_NAME: &str = "__output";
+
+const OUTPUT_SHIM: &str = include_str!("output.js");
+
+/// The script that routes guest output through the output op, see
+/// output.js.
+pub fn output_shim() -> String {
+    format!("{}({});", OUTPUT_SHIM.trim_end(), UTF16_SHIM.trim_end())
+}
+
+/// Where guest `Deno.core.print` and console output goes.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Write to the host process's stdout and stderr.
+    Inherit,
+    Discard,
+    /// Keep it until the host reads it with isolateReadOutput.
+    Buffer,
+    /// Hand it to the host as it arrives through isolateAwaitOutput.
+    Stream,
+}
+
+impl Default for OutputMode {
+    fn default() -> Self {
+        OutputMode::Inherit
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize)]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub text: String,
+}
+
+/// Captured output of one isolate. Written from the isolate's thread by the
+/// internal output op, read by the host.
+pub struct Output {
+    mode: OutputMode,
+    chunks: Mutex<VecDeque<OutputChunk>>,
+    waker: AtomicWaker,
+    closed: AtomicBool,
+}
+
+impl Output {
+    pub fn new(mode: OutputMode) -> Self {
+        Self {
+            mode,
+            chunks: Mutex::new(VecDeque::new()),
+            waker: AtomicWaker::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Handle a call to the output op. `control` holds the stream, 0 for
+    /// stdout and 1 for stderr, `zero_copy` the text as encoded by
+    /// UTF16_SHIM.
+    pub fn record(&self, control: &[u8], zero_copy: Option<PinnedBuf>) -> CoreOp {
+        let stream = match control.first() {
+            Some(1) => OutputStream::Stderr,
+            _ => OutputStream::Stdout,
+        };
+        let text = zero_copy
+            .map(|buf| String::from_utf16_lossy(&utf16_units(&buf)))
+            .unwrap_or_default();
+        match self.mode {
+            OutputMode::Inherit => {
+                // Same as deno_core's own print.
+                match stream {
+                    OutputStream::Stdout => {
+                        print!("{}", text);
+                        let _ = std::io::stdout().flush();
+                    }
+                    OutputStream::Stderr => {
+                        eprint!("{}", text);
+                        let _ = std::io::stderr().flush();
+                    }
+                }
+            }
+            OutputMode::Discard => {}
+            OutputMode::Buffer | OutputMode::Stream => {
+                self.chunks
+                    .lock()
+                    .unwrap()
+                    .push_back(OutputChunk { stream, text });
+                self.waker.wake();
+            }
+        }
+        Op::Sync(Vec::new().into_boxed_slice())
+    }
+
+    /// Everything captured since the last take.
+    pub fn take(&self) -> Vec<OutputChunk> {
+        self.chunks.lock().unwrap().drain(..).collect()
+    }
+
+    /// Stop streaming, waking a host waiting for output.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
+/// Resolves with the next output of an isolate, or `{closed: true}` once
+/// the isolate is closed and everything before was read.
+pub struct OutputWorker {
+    pub output: Arc<Output>,
+}
+
+impl Future for OutputWorker {
+    type Output = Result<Value, ErrBox>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let output = &self.output;
+        output.waker.register(cx.waker());
+        // Read closed first so output recorded before the close is not lost.
+        let closed = output.closed.load(Ordering::SeqCst);
+        let chunks = output.take();
+        if !chunks.is_empty() {
+            return Poll::Ready(Ok(json!({ "chunks": chunks })));
+        }
+        if closed {
+            return Poll::Ready(Ok(json!({ "closed": true })));
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,170 @@
+// Modeled after deno_core's ResourceTable, with the addition that rids carry
+// a generation so a stale rid never resolves to a newer resource that reused
+// its slot. Freed slots are reused oldest first, and a slot whose generation
+// runs out is retired rather than wrapped, so a rid is never handed out twice.
+use crate::msg::ResourceId;
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use deno_dispatch_json::JsonOp;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref RESOURCE_TABLE: Mutex<ResourceTable> = Mutex::new(ResourceTable::default());
+}
+
+const INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: u32 = (1 << (32 - INDEX_BITS)) - 1;
+
+/// A value that can be stored in the resource table. `KIND` tags the entry,
+/// it is reported by the resources op and used in lookup errors.
+pub trait Resource: Any + Send {
+    const KIND: &'static str;
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<(&'static str, Box<dyn Any + Send>)>,
+}
+
+#[derive(Default)]
+pub struct ResourceTable {
+    slots: Vec<Slot>,
+    free: VecDeque<u32>,
+}
+
+impl ResourceTable {
+    fn slot(&self, rid: ResourceId) -> Option<&Slot> {
+        let slot = self.slots.get((rid & INDEX_MASK) as usize)?;
+        if slot.generation == rid >> INDEX_BITS && slot.entry.is_some() {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    /// Returns None once every slot is either in use or retired.
+    pub fn add<T: Resource>(&mut self, resource: T) -> Option<ResourceId> {
+        let index = match self.free.pop_front() {
+            Some(index) => index,
+            None => {
+                let index = self.slots.len() as u32;
+                if index > INDEX_MASK {
+                    return None;
+                }
+                // Generations start at 1 so no rid is ever 0.
+                self.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                index
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.entry = Some((T::KIND, Box::new(resource)));
+        Some((slot.generation << INDEX_BITS) | index)
+    }
+
+    pub fn get<T: Resource>(&self, rid: ResourceId) -> Option<&T> {
+        let (_, resource) = self.slot(rid)?.entry.as_ref()?;
+        resource.downcast_ref::<T>()
+    }
+
+    pub fn kind(&self, rid: ResourceId) -> Option<&'static str> {
+        self.slot(rid)?.entry.as_ref().map(|(kind, _)| *kind)
+    }
+
+    pub fn entries(&self) -> Vec<(ResourceId, &'static str)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                slot.entry
+                    .as_ref()
+                    .map(|(kind, _)| ((slot.generation << INDEX_BITS) | index as u32, *kind))
+            })
+            .collect()
+    }
+
+    pub fn close<T: Resource>(&mut self, rid: ResourceId) -> Option<T> {
+        self.get::<T>(rid)?;
+        let index = rid & INDEX_MASK;
+        let slot = &mut self.slots[index as usize];
+        let (_, resource) = slot.entry.take()?;
+        if slot.generation < GENERATION_MASK {
+            slot.generation += 1;
+            self.free.push_back(index);
+        }
+        resource.downcast::<T>().ok().map(|r| *r)
+    }
+}
+
+fn bad_resource_kind(table: &ResourceTable, kind: &str, rid: ResourceId) -> ErrBox {
+    match table.kind(rid) {
+        Some(actual) => ErrBox::from(JsonError::new(
+            "BadResource",
+            format!("resource id {} is a {}, expected a {}", rid, actual, kind),
+        )),
+        None => bad_resource(kind, rid),
+    }
+}
+
+pub fn bad_resource(kind: &str, rid: ResourceId) -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "BadResource",
+        format!("bad {} resource id: {}", kind, rid),
+    ))
+}
+
+/// Store `resource` and return its rid, or fail with a ResourceTableFull
+/// error if no slot is left.
+pub fn add_resource<T: Resource>(resource: T) -> Result<ResourceId, ErrBox> {
+    let mut table = RESOURCE_TABLE.lock().unwrap();
+    table.add(resource).ok_or_else(|| {
+        ErrBox::from(JsonError::new(
+            "ResourceTableFull",
+            format!("no resource id left for a {}", T::KIND),
+        ))
+    })
+}
+
+/// Clone the resource stored under `rid`, or fail with a BadResource error
+/// if it is missing or of another kind.
+pub fn get_resource<T: Resource + Clone>(rid: ResourceId) -> Result<T, ErrBox> {
+    let table = RESOURCE_TABLE.lock().unwrap();
+    match table.get::<T>(rid) {
+        Some(resource) => Ok(resource.clone()),
+        None => Err(bad_resource_kind(&table, T::KIND, rid)),
+    }
+}
+
+/// Remove the resource stored under `rid`, or fail with a BadResource error
+/// if it is missing or of another kind.
+pub fn take_resource<T: Resource>(rid: ResourceId) -> Result<T, ErrBox> {
+    let mut table = RESOURCE_TABLE.lock().unwrap();
+    match table.close::<T>(rid) {
+        Some(resource) => Ok(resource),
+        None => Err(bad_resource_kind(&table, T::KIND, rid)),
+    }
+}
+
+#[derive(Serialize)]
+struct ResourceEntry {
+    pub rid: ResourceId,
+    pub kind: &'static str,
+}
+
+pub fn op_resources(_args: Value, _zero_copy: Option<PinnedBuf>) -> Result<JsonOp, ErrBox> {
+    let table = RESOURCE_TABLE.lock().unwrap();
+    let entries: Vec<ResourceEntry> = table
+        .entries()
+        .into_iter()
+        .map(|(rid, kind)| ResourceEntry { rid, kind })
+        .collect();
+    Ok(JsonOp::Sync(json!(entries)))
+}
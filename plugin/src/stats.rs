@@ -0,0 +1,132 @@
+use deno_core::*;
+use futures::future::FutureExt;
+use serde::Serialize;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Runtime counters for one guest isolate.
+///
+/// Heap statistics are missing. deno_core doesn't give us access to the
+/// underlying V8 isolate, so they can't be reported until it exposes
+/// get_heap_statistics.
+#[derive(Default)]
+pub struct Stats {
+    registered_ops: AtomicUsize,
+    pending_ops: AtomicUsize,
+    queued_calls: AtomicUsize,
+    busy_time_us: AtomicU64,
+    cpu_time_us: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub registered_ops: usize,
+    pub pending_ops: usize,
+    pub queued_calls: usize,
+    pub busy_time_us: u64,
+    /// None where thread CPU time can't be measured.
+    pub cpu_time_us: Option<u64>,
+}
+
+/// CPU time used by the calling thread so far.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    if result == 0 {
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+struct PendingOpGuard(Arc<Stats>);
+
+impl Drop for PendingOpGuard {
+    fn drop(&mut self) {
+        self.0.pending_ops.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Stats {
+    pub fn op_registered(&self) {
+        self.registered_ops.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn op_unregistered(&self) {
+        self.registered_ops.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn call_queued(&self) {
+        self.queued_calls.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn call_done(&self) {
+        self.queued_calls.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Calls sent to the isolate's thread that haven't finished, including
+    /// the one running.
+    pub fn queued_calls(&self) -> usize {
+        self.queued_calls.load(Ordering::SeqCst)
+    }
+
+    /// Count `op` as pending until its future resolves or is dropped.
+    pub fn track_op(self: &Arc<Self>, op: CoreOp) -> CoreOp {
+        match op {
+            Op::Async(fut) => {
+                self.pending_ops.fetch_add(1, Ordering::SeqCst);
+                let guard = PendingOpGuard(self.clone());
+                Op::Async(
+                    fut.map(move |result| {
+                        drop(guard);
+                        result
+                    })
+                    .boxed(),
+                )
+            }
+            op => op,
+        }
+    }
+
+    /// Run `f` and add the time it took to the isolate's busy time. This is
+    /// wall clock time on the thread driving the isolate, so it includes
+    /// time sync ops spend waiting on the host. The thread's CPU time over
+    /// the same stretch, which leaves such waits out, is counted separately.
+    /// Must be called on the thread driving the isolate.
+    pub fn time<R>(&self, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let cpu_start = thread_cpu_time();
+        let result = f();
+        self.busy_time_us
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::SeqCst);
+        if let (Some(cpu_start), Some(cpu_end)) = (cpu_start, thread_cpu_time()) {
+            let cpu_time = cpu_end.checked_sub(cpu_start).unwrap_or_default();
+            self.cpu_time_us
+                .fetch_add(cpu_time.as_micros() as u64, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn snapshot(&self) -> StatsResponse {
+        StatsResponse {
+            registered_ops: self.registered_ops.load(Ordering::SeqCst),
+            pending_ops: self.pending_ops.load(Ordering::SeqCst),
+            queued_calls: self.queued_calls(),
+            busy_time_us: self.busy_time_us.load(Ordering::SeqCst),
+            cpu_time_us: thread_cpu_time().map(|_| self.cpu_time_us.load(Ordering::SeqCst)),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::Once;
+use std::thread;
+use std::thread::Thread;
+use std::time::Duration;
+use std::time::Instant;
+
+pub fn terminated_json_error() -> JsonError {
+    JsonError::new("Terminated", "guest isolate was terminated".to_string())
+}
+
+pub fn terminated_error() -> ErrBox {
+    ErrBox::from(terminated_json_error())
+}
+
+/// Thread-safe handle for forcefully stopping guest code. Once terminated
+/// an isolate is considered dead and every later call on it fails with a
+/// "Terminated" error.
+pub struct Termination {
+    handle: IsolateHandle,
+    thread: Thread,
+    terminated: AtomicBool,
+}
+
+impl Termination {
+    /// `thread` is the thread driving the isolate, it is woken on
+    /// termination so it can fail anyone waiting on the event loop.
+    pub fn new(handle: IsolateHandle, thread: Thread) -> Self {
+        Self {
+            handle,
+            thread,
+            terminated: AtomicBool::new(false),
+        }
+    }
+
+    pub fn terminate(&self) {
+        self.terminated.store(true, Ordering::SeqCst);
+        self.handle.terminate_execution();
+        self.thread.unpark();
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated.load(Ordering::SeqCst)
+    }
+
+    /// Fail early if the isolate is already dead.
+    pub fn check(&self) -> Result<(), ErrBox> {
+        if self.is_terminated() {
+            Err(terminated_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Errors caused by termination surface as "Terminated" rather than
+    /// whatever V8 reported for the interrupted script.
+    pub fn map_error(&self, err: ErrBox) -> ErrBox {
+        if self.is_terminated() {
+            terminated_error()
+        } else {
+            err
+        }
+    }
+}
+
+struct WatchdogState {
+    deadline: Option<Instant>,
+    stopped: bool,
+}
+
+/// Terminates the isolate if it is still running guest code when an armed
+/// deadline passes. Calls and event loop polls arm and disarm it over and
+/// over, so a single long lived thread serves them all rather than one
+/// thread per call. The thread is started the first time the watchdog is
+/// armed and stops when the watchdog is dropped.
+pub struct Watchdog {
+    termination: Arc<Termination>,
+    state: Arc<(Mutex<WatchdogState>, Condvar)>,
+    started: Once,
+}
+
+impl Watchdog {
+    pub fn new(termination: Arc<Termination>) -> Self {
+        Self {
+            termination,
+            state: Arc::new((
+                Mutex::new(WatchdogState {
+                    deadline: None,
+                    stopped: false,
+                }),
+                Condvar::new(),
+            )),
+            started: Once::new(),
+        }
+    }
+
+    fn spawn(&self) {
+        let state = self.state.clone();
+        let termination = self.termination.clone();
+        thread::spawn(move || {
+            let (lock, armed) = &*state;
+            let mut state = lock.lock().unwrap();
+            while !state.stopped {
+                state = match state.deadline {
+                    None => armed.wait(state).unwrap(),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            state.deadline = None;
+                            termination.terminate();
+                            state
+                        } else {
+                            armed.wait_timeout(state, deadline - now).unwrap().0
+                        }
+                    }
+                };
+            }
+        });
+    }
+
+    pub fn termination(&self) -> &Arc<Termination> {
+        &self.termination
+    }
+
+    /// Terminate the isolate unless `disarm` is called within `timeout`.
+    pub fn arm(&self, timeout: Duration) {
+        self.started.call_once(|| self.spawn());
+        self.set(|state| state.deadline = Some(Instant::now() + timeout));
+    }
+
+    /// Like `arm`, but disarmed when the returned guard is dropped.
+    pub fn arm_scoped(&self, timeout: Duration) -> ArmedWatchdog<'_> {
+        self.arm(timeout);
+        ArmedWatchdog { watchdog: self }
+    }
+
+    pub fn disarm(&self) {
+        self.set(|state| state.deadline = None);
+    }
+
+    fn set(&self, update: impl FnOnce(&mut WatchdogState)) {
+        let (lock, armed) = &*self.state;
+        update(&mut lock.lock().unwrap());
+        armed.notify_one();
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.set(|state| state.stopped = true);
+    }
+}
+
+/// See `Watchdog::arm_scoped`.
+pub struct ArmedWatchdog<'a> {
+    watchdog: &'a Watchdog,
+}
+
+impl Drop for ArmedWatchdog<'_> {
+    fn drop(&mut self) {
+        self.watchdog.disarm();
+    }
+}
@@ -0,0 +1,10 @@
+// Encodes a string as UTF-16 code units for the plugin, since guests have no
+// TextEncoder. Passed to the shims that send text to the plugin, which reads
+// it back with util::utf16_units.
+(text => {
+  const chars = new Uint16Array(text.length);
+  for (let i = 0; i < text.length; i++) {
+    chars[i] = text.charCodeAt(i);
+  }
+  return chars;
+})
@@ -0,0 +1,47 @@
+use futures::future::FutureExt;
+use futures::task::ArcWake;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread::Thread;
+
+/// Encoder guest shims take as an argument to send text, see utf16.js.
+pub const UTF16_SHIM: &str = include_str!("utf16.js");
+
+struct ThreadWaker(Thread);
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+/// A waker that unparks the current thread.
+pub fn thread_waker() -> Waker {
+    futures::task::waker(Arc::new(ThreadWaker(std::thread::current())))
+}
+
+/// Block the current thread until `fut` resolves, parking between polls.
+/// Unlike `futures::executor::block_on` this may be called from inside
+/// another executor, which is where guest ops are dispatched from.
+pub fn park_on<F: Future + Unpin>(mut fut: F) -> F::Output {
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.poll_unpin(&mut cx) {
+            Poll::Ready(v) => return v,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// Code units of text a guest shim encoded with UTF16_SHIM. A Uint16Array
+/// writes them in native byte order, and guests always share the process
+/// with the plugin.
+pub fn utf16_units(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .collect()
+}
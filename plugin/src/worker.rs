@@ -0,0 +1,226 @@
+use crate::errors::guest_json_error;
+use crate::stats::Stats;
+use crate::termination::terminated_json_error;
+use crate::termination::Termination;
+use crate::termination::Watchdog;
+use crate::util::park_on;
+use crate::util::thread_waker;
+use deno_core::*;
+use deno_dispatch_json::JsonError;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::future::FutureExt;
+use futures::stream::StreamExt;
+use futures::task::Context;
+use futures::task::Poll;
+use std::future::Future;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+type Task = Box<dyn FnOnce(&mut Box<EsIsolate>) + Send>;
+
+enum Command {
+    Run(Task),
+    AwaitComplete(Waiter),
+}
+
+/// Someone waiting for the event loop to complete. `budget` is how much
+/// longer the event loop may spend running guest code on their behalf.
+struct Waiter {
+    done: oneshot::Sender<Result<(), JsonError>>,
+    budget: Option<Duration>,
+}
+
+fn worker_exited_error() -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "WorkerExited",
+        "guest isolate thread exited".to_string(),
+    ))
+}
+
+pub fn busy_error() -> ErrBox {
+    ErrBox::from(JsonError::new(
+        "IsolateBusy",
+        "guest isolate is busy with another call".to_string(),
+    ))
+}
+
+/// Owns a guest isolate on its own OS thread. V8 isolates must not move
+/// between threads, so every call is sent to the thread as a command and
+/// run there in order. Between commands the thread drives the isolate's
+/// event loop for as long as someone is waiting for it to complete.
+///
+/// The thread exits, dropping the isolate, once every handle is dropped and
+/// the commands already queued have run.
+#[derive(Clone)]
+pub struct Worker {
+    commands: mpsc::UnboundedSender<Command>,
+    stats: Arc<Stats>,
+    watchdog: Arc<Watchdog>,
+}
+
+impl Worker {
+    /// Start a thread and build the isolate on it with `create`. `T` is
+    /// anything else `create` wants to hand back to the caller. `keep_alive`
+    /// is dropped only after the isolate, for memory the isolate borrows
+    /// such as its startup snapshot. Event loop polls are timed in `stats`.
+    pub fn spawn<T, K, F>(stats: Arc<Stats>, keep_alive: K, create: F) -> Result<(Self, T), ErrBox>
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+        F: FnOnce() -> Result<(Box<EsIsolate>, T), ErrBox> + Send + 'static,
+    {
+        let (commands, commands_rx) = mpsc::unbounded::<Command>();
+        let stats_ = stats.clone();
+        let (created_tx, created_rx) = oneshot::channel::<Result<(Arc<Watchdog>, T), ErrBox>>();
+        thread::Builder::new()
+            .name("guest isolate".to_string())
+            .spawn(move || match create() {
+                Ok((mut isolate, extra)) => {
+                    let termination = Arc::new(Termination::new(
+                        isolate.shared_isolate_handle(),
+                        thread::current(),
+                    ));
+                    let watchdog = Arc::new(Watchdog::new(termination));
+                    let _ = created_tx.send(Ok((watchdog.clone(), extra)));
+                    run_loop(isolate, commands_rx, stats_, watchdog);
+                    drop(keep_alive);
+                }
+                Err(err) => {
+                    let _ = created_tx.send(Err(err));
+                }
+            })?;
+        let (watchdog, extra) = park_on(created_rx).map_err(|_| worker_exited_error())??;
+        let worker = Self {
+            commands,
+            stats,
+            watchdog,
+        };
+        Ok((worker, extra))
+    }
+
+    pub fn termination(&self) -> &Arc<Termination> {
+        self.watchdog.termination()
+    }
+
+    /// The isolate's watchdog, for calls to arm while they run guest code.
+    /// The thread arms it for event loop polls too, see `await_complete`.
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.watchdog
+    }
+
+    /// True while a call is running on the isolate or waiting to.
+    pub fn is_busy(&self) -> bool {
+        self.stats.queued_calls() > 0
+    }
+
+    /// Run `task` on the isolate once every call queued before it is done.
+    /// Calls run in the order they were made.
+    pub fn call<R, F>(&self, task: F) -> impl Future<Output = Result<R, ErrBox>>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Box<EsIsolate>) -> R + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel::<R>();
+        let stats = self.stats.clone();
+        self.stats.call_queued();
+        let sent = self
+            .commands
+            .unbounded_send(Command::Run(Box::new(move |isolate| {
+                let _ = result_tx.send(task(isolate));
+                stats.call_done();
+            })));
+        if sent.is_err() {
+            self.stats.call_done();
+        }
+        async move {
+            sent.map_err(|_| worker_exited_error())?;
+            result_rx.await.map_err(|_| worker_exited_error())
+        }
+    }
+
+    /// Resolves once the isolate's event loop has nothing left to do, that
+    /// is no pending ops or dynamic imports. The thread only polls the event
+    /// loop while someone is waiting, and parks until a command arrives or
+    /// an op or import it is waiting on completes.
+    ///
+    /// With a `timeout` the isolate is terminated once polling the event
+    /// loop, that is running op callbacks and other guest code, has taken
+    /// longer than that in total. Time spent parked waiting for ops doesn't
+    /// count.
+    pub fn await_complete(
+        &self,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<(), ErrBox>> {
+        let (done_tx, done_rx) = oneshot::channel();
+        let sent = self.commands.unbounded_send(Command::AwaitComplete(Waiter {
+            done: done_tx,
+            budget: timeout,
+        }));
+        async move {
+            sent.map_err(|_| worker_exited_error())?;
+            done_rx
+                .map(|result| match result {
+                    Ok(result) => result.map_err(ErrBox::from),
+                    Err(_) => Err(worker_exited_error()),
+                })
+                .await
+        }
+    }
+}
+
+fn run_loop(
+    mut isolate: Box<EsIsolate>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    stats: Arc<Stats>,
+    watchdog: Arc<Watchdog>,
+) {
+    let termination = watchdog.termination();
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut waiters: Vec<Waiter> = Vec::new();
+    loop {
+        loop {
+            match commands.poll_next_unpin(&mut cx) {
+                Poll::Ready(Some(Command::Run(task))) => task(&mut isolate),
+                Poll::Ready(Some(Command::AwaitComplete(waiter))) => waiters.push(waiter),
+                Poll::Ready(None) => return,
+                Poll::Pending => break,
+            }
+        }
+        if termination.is_terminated() {
+            // Pending ops of a terminated isolate may never complete, don't
+            // leave anyone waiting on them.
+            for waiter in waiters.drain(..) {
+                let _ = waiter.done.send(Err(terminated_json_error()));
+            }
+        } else if !waiters.is_empty() {
+            let budget = waiters.iter().filter_map(|waiter| waiter.budget).min();
+            let armed = budget.map(|budget| watchdog.arm_scoped(budget));
+            let started = Instant::now();
+            let poll = stats.time(|| isolate.poll_unpin(&mut cx));
+            drop(armed);
+            let elapsed = started.elapsed();
+            for waiter in waiters.iter_mut() {
+                waiter.budget = waiter.budget.map(|budget| budget.saturating_sub(elapsed));
+            }
+            if termination.is_terminated() {
+                // The watchdog fired mid poll, fail the waiters on the next
+                // iteration.
+                continue;
+            }
+            if let Poll::Ready(result) = poll {
+                let result = result.map_err(guest_json_error);
+                for waiter in waiters.drain(..) {
+                    let _ = waiter.done.send(result.clone());
+                }
+                // The event loop finished, check for new commands before
+                // parking.
+                continue;
+            }
+        }
+        thread::park();
+    }
+}
@@ -7,6 +7,8 @@ use futures::future::FutureExt;
 pub use serde_derive::Deserialize;
 use serde_json::json;
 pub use serde_json::Value;
+use std::error::Error;
+use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
 
@@ -17,10 +19,50 @@ pub enum JsonOp {
     Async(AsyncJsonOp),
 }
 
+/// Error with a `kind` that is passed through to JS next to its message, so
+/// callers can tell failures apart without matching on message text. `data`
+/// carries any structured details that belong with the error.
+#[derive(Clone, Debug)]
+pub struct JsonError {
+    pub kind: String,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl JsonError {
+    pub fn new(kind: &str, message: String) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message,
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl Error for JsonError {}
+
 fn json_err(err: ErrBox) -> Value {
-    json!({
-        "message": err.to_string(),
-    })
+    match err.downcast_ref::<JsonError>() {
+        Some(json_error) => json!({
+            "kind": json_error.kind,
+            "message": json_error.message,
+            "data": json_error.data,
+        }),
+        None => json!({
+            "message": err.to_string(),
+        }),
+    }
 }
 
 fn serialize_result(promise_id: Option<u64>, result: Result<Value, ErrBox>) -> Buf {
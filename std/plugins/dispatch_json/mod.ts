@@ -5,7 +5,21 @@ import { assert, Deferred, deferred } from "./../deps.ts";
 type Ok = any;
 
 interface JsonError {
+  kind?: string;
   message: string;
+  data?: unknown;
+}
+
+// Thrown for op errors that carry a kind, e.g. "BadResource".
+export class JsonOpError extends Error {
+  constructor(
+    readonly kind: string,
+    message: string,
+    readonly data?: unknown
+  ) {
+    super(message);
+    this.name = kind;
+  }
 }
 
 interface JsonResponse {
@@ -26,6 +40,9 @@ function encode(args: object): Uint8Array {
 
 function unwrapResponse(res: JsonResponse): Ok {
   if (res.err != null) {
+    if (res.err!.kind != null) {
+      throw new JsonOpError(res.err!.kind!, res.err!.message, res.err!.data);
+    }
     throw new Error(res.err!.message);
   }
   assert(res.ok != null);
@@ -1,3 +1,7 @@
 // Copyright 2018-2019 the Deno authors. All rights reserved. MIT license.
 export * from "./plugin_filename.ts";
-export { jsonOp, DispatchJsonPluginOp } from "./dispatch_json/mod.ts";
+export {
+  jsonOp,
+  DispatchJsonPluginOp,
+  JsonOpError
+} from "./dispatch_json/mod.ts";